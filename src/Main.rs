@@ -1,24 +1,21 @@
+// camelCase names are the style of this crate, and lib is its module of simulation code
+#![allow(non_snake_case, special_module_name)]
 
 
 
-use futures::FutureExt;
 use winit::{
     event::*,
     event_loop::{ControlFlow, EventLoop},
-    window::{Window, WindowBuilder},
 };
-use wgpu::util::DeviceExt;
 
-use array_macro::array;
-
-use std::{borrow::Cow, iter, num::{NonZeroU64, NonZeroU32}, array, any::TypeId};
-
-use crate::lib::{network::Network, activations::SIGMOID, sirmodel::SIRModel, trainer::{Trainer, TrainModel}};
-use lib::{person::Personstate, wgpuInit::{self, WgpuInit}};
+use crate::lib::{network::Network, activations::SIGMOID, sirmodel::{SIRModel, Observable}, trainer::{Trainer, TrainModel}, alignment::{DataAlignment, FitWindow}};
+// used by the commented out examples in main
+#[allow(unused_imports)]
+use crate::lib::{distributions::Distribution, strain::Strain, movement::{Movement, MovementModel}, venue::Venue, parameter::Parameter, kernel::{Matern, Smoothness}, bayesian::BatchStrategy, loss::Loss, alignment::observationsFromCsv, sirmodel::Scaling};
+use lib::wgpuInit::WgpuInit;
 use std::error::Error;
 use std::fs::File;
 use std::io::{self, BufRead};
-use std::path::Path;
 
 pub mod lib;
 
@@ -232,55 +229,83 @@ async fn main() {
     env_logger::init();
     let size = &window.inner_size();
 
-    let wgpuinit = WgpuInit::new(size.clone(),instance,surface).await;
+    let wgpuinit = WgpuInit::new(*size,instance,surface).await;
 
     let days = 100;
 
-    let mut expectedPopSize = 9633740;
+    let expectedPopSize = 9633740;
     //let mut expectedPopSize = 4968000;
     //let mut expectedPopSize = 10350000;
 
-    let mut populationSize = 60000;
+    let populationSize = 60000;
 
     let probability = 0.5311931876496908;
 
+    // mutable for the commented out examples
+    #[allow(unused_mut)]
     let mut simulation = SIRModel::new(populationSize, 7.0,14,5,probability,0.05,100000.0,100000.0,10.0,300.0,days, wgpuinit,Vec::new());
+    //simulation.setInfectiousness(Distribution::Gamma { mean: 1.0, dispersion: 0.1 });
+    //simulation.addStrain(Strain::new(1.5, 10, 0.3, 40, 5));
+    //simulation.setDemography(0.00004, 0.00003, 70000);
+    //simulation.setMovement(Movement::new(MovementModel::HomeAnchored));
+    //simulation.setVenues(Venue::fromCsv("src/venues.csv").unwrap());
+    //simulation.recordTransmissions(true);
+    //simulation.setScaling(Scaling::AgentWeight((expectedPopSize as f64) / (populationSize as f64)));
+    //simulation.clearOut(5);
 
     let mutdat = Arc::new(Mutex::new(simulation));
     let thdat1 = Arc::clone(&mutdat);
     let thdat2 = Arc::clone(&mutdat);
     let thdat3 = Arc::clone(&mutdat);
 
-    let mut optimal = 0.0;
+    let mut optimal = Vec::new();
 
     if let Err(err) = read_csv_file("src/belarus.csv") {
         eprintln!("Error: {}", err);
     }
 
-    let mut startingData = vec![read_csv_file("src/belarus.csv").unwrap()];
+    let startingData = vec![read_csv_file("src/belarus.csv").unwrap()];
     
 
     //let mut startingData = vec![vec![3,5,10,25,60]];
 
-    let mut trainsim = async {
+    let _trainsim = async {
         let mut trainer = Trainer::new(thdat3, 10,10, startingData,  Vec::new(), TrainModel::Bayesian, 0.1, expectedPopSize, populationSize);
+        // belarus.csv holds cumulative reported cases
+        trainer.setObservable(Observable::CumulativeReported);
+        //trainer.setKernel(Box::new(Matern::new(Smoothness::FiveHalves, vec![0.2; 3], 1.0)));
+        //trainer.setParameters(vec![Parameter::new("spread", 0.0, 1.0), Parameter::new("infRad", 2.0, 20.0), Parameter::logScaled("startInfNum", 1.0, 50.0)]);
+        //trainer.setBatch(4, BatchStrategy::KrigingBeliever, 4);
+        //trainer.setLoss(Loss::NegativeBinomial(10.0));
+        // fit the first 40 days, the data starting on the first simulated day
+        trainer.setAlignment(DataAlignment::new(0, FitWindow::FirstN(40)));
+        //trainer.setObserved(observationsFromCsv("src/belarus.csv", false).unwrap().1);
         optimal = trainer.train(expectedPopSize).await;
         println!("Optimal: {:?}", optimal);
     };
 
     let mut t: usize = 0;
 
-    let mut runsim = async {
-        let mut simul = thdat1.lock().unwrap();
+    let runsim = async {
+        // run a copy so the lock is not held while the simulation is awaited
+        let mut simul = thdat1.lock().unwrap().clone();
         println!("this is running");
-        runsimulation(&mut *simul).await;
+        runsimulation(&mut simul).await;
         println!("{:?}", simul.numInfected());
+        println!("New infections: {:?}", simul.newInfections());
+        println!("Attack rate: {:?}, peak (day, size): {:?}", simul.attackRate(), simul.peak());
         println!("{:?}", simul.getrnaught());
+        println!("R_t (Cori, 7 day window): {:?}", simul.rtCori(7).iter().map(|r| r.mean).collect::<Vec<f64>>());
+        println!("Offspring distribution: {:?}", simul.offspringDistribution());
+        println!("Strain incidence: {:?}", simul.strainIncidence());
+        println!("Imported: {:?}", simul.getImported());
+        // the event loop draws the finished run
+        *thdat1.lock().unwrap() = simul;
     };
 
-    let mut eventloopfuture = async {
+    let eventloopfuture = async {
         let mut simul = thdat2.lock().unwrap();
-        event_loop.run(move |event, control_flow| {
+        let _ = event_loop.run(move |event, control_flow| {
             control_flow.set_control_flow(ControlFlow::Poll);
             
             match event {
@@ -295,7 +320,7 @@ async fn main() {
                             simul.newFrame(t);
                             println!("This ran as well");
                             if t <days {
-                                t = t+1
+                                t += 1
                             } else {
                                 t = 0;
                             } 
@@ -310,7 +335,7 @@ async fn main() {
         });
     };
 
-    //trainsim.await;
+    //_trainsim.await;
     runsim.await;
     eventloopfuture.await;
     
@@ -372,7 +397,7 @@ async fn  runsimulation(simul: &mut SIRModel) {
 fn read_csv_file(file_path: &str) -> Result<Vec<usize>, Box<dyn Error>> {
     let file = File::open(file_path)?;
     let mut numbers = Vec::new();
-    let mut reader = io::BufReader::new(file);
+    let reader = io::BufReader::new(file);
 
     for line in reader.lines() {
         let line = line?;
@@ -0,0 +1,224 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufRead};
+
+// Days of the observed series used in a fit, counted from its first entry
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum FitWindow {
+    All,
+    FirstN(usize),
+    // from the first day up to but not including the second
+    Range(usize, usize)
+}
+
+// A calendar day, read from YYYY-MM-DD
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+pub struct Date {
+    // days since 1970-01-01
+    day: i64
+}
+
+impl Date {
+    pub fn new(year: i64, month: i64, day: i64) -> Option<Date> {
+        if !(1..=12).contains(&month) || day < 1 || day > daysInMonth(year, month) {
+            return None;
+        }
+        // days from civil, with years starting in March so the leap day comes last
+        let y = if month <= 2 { year - 1 } else { year };
+        let era = y.div_euclid(400);
+        let yearOfEra = y - era * 400;
+        let dayOfYear = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+        let dayOfEra = yearOfEra * 365 + yearOfEra / 4 - yearOfEra / 100 + dayOfYear;
+        Some(Date {day: era * 146097 + dayOfEra - 719468})
+    }
+
+    pub fn parse(text: &str) -> Option<Date> {
+        let parts: Vec<&str> = text.trim().split('-').collect();
+        if parts.len() != 3 || parts[0].len() != 4 {
+            return None;
+        }
+        let mut nums = [0i64;3];
+        for i in 0..3 {
+            nums[i] = parts[i].parse().ok()?;
+        }
+        Date::new(nums[0], nums[1], nums[2])
+    }
+
+    // Days from other to self, negative if other is later
+    pub fn daysSince(&self, other: Date) -> i64 {
+        self.day - other.day
+    }
+}
+
+fn daysInMonth(year: i64, month: i64) -> i64 {
+    match month {
+        2 if (year % 4 == 0 && year % 100 != 0) || year % 400 == 0 => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31
+    }
+}
+
+// Which simulated day each observation is compared with
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct DataAlignment {
+    // simulated day of the first observation, for data that starts after the seeding
+    startOffset: usize,
+    window: FitWindow
+}
+
+impl DataAlignment {
+    pub fn new(startOffset: usize, window: FitWindow) -> DataAlignment {
+        DataAlignment {startOffset, window}
+    }
+
+    // Fits the days from first to last inclusive, for data starting on dataStart in a simulation whose
+    // day 0 is simulationStart. None if the data starts before the simulation or the range is empty
+    pub fn fromDates(simulationStart: Date, dataStart: Date, first: Date, last: Date) -> Option<DataAlignment> {
+        let startOffset = dataStart.daysSince(simulationStart);
+        if startOffset < 0 || last < first || last < dataStart {
+            return None;
+        }
+        let start = first.daysSince(dataStart).max(0) as usize;
+        let end = last.daysSince(dataStart) as usize + 1;
+        Some(DataAlignment::new(startOffset as usize, FitWindow::Range(start, end)))
+    }
+
+    // Observed days in the window, cut to the length of the data
+    fn days(&self, observedLen: usize) -> (usize, usize) {
+        let (start, end) = match self.window {
+            FitWindow::All => (0, observedLen),
+            FitWindow::FirstN(n) => (0, n),
+            FitWindow::Range(start, end) => (start, end)
+        };
+        let end = end.min(observedLen);
+        (start.min(end), end)
+    }
+
+    // Days to simulate so the last observation in the window has a simulated day
+    pub fn horizon(&self, observedLen: usize) -> usize {
+        let (_, end) = self.days(observedLen);
+        (self.startOffset + end).max(1)
+    }
+
+    // Observed and simulated values of the days in the window with an observation,
+    // ready for Loss::evaluate
+    pub fn align(&self, observed: &[Option<f64>], simulated: &[f64]) -> (Vec<f64>, Vec<f64>) {
+        let (start, end) = self.days(observed.len());
+        let mut obs = Vec::new();
+        let mut sim = Vec::new();
+        for (day, value) in observed.iter().enumerate().take(end).skip(start) {
+            if let (Some(o), Some(s)) = (value, simulated.get(self.startOffset + day)) {
+                obs.push(*o);
+                sim.push(*s);
+            }
+        }
+        (obs, sim)
+    }
+}
+
+// First date, if the file has dates, and a value or None for each day from it
+pub type DatedObservations = (Option<Date>, Vec<Option<f64>>);
+
+// Counts with every day observed
+pub fn fromCounts(counts: &[usize]) -> Vec<Option<f64>> {
+    counts.iter().map(|c| Some(*c as f64)).collect()
+}
+
+// Reads daily values from the last column, after a header line if there is one. Empty or
+// non-numeric values, such as NA, are missing days. When the first column holds YYYY-MM-DD dates
+// the values are placed by date from the first one, which is returned, and skipped dates are
+// missing days. Otherwise there is one day per line
+pub fn observationsFromCsv(file_path: &str, header: bool) -> Result<DatedObservations, Box<dyn Error>> {
+    let file = File::open(file_path)?;
+    let mut values = Vec::new();
+    let mut start: Option<Date> = None;
+    let reader = io::BufReader::new(file);
+
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if (header && i == 0) || line.trim().is_empty() {
+            continue;
+        }
+        let mut columns = line.trim().split(',');
+        let date = Date::parse(columns.next().unwrap_or(""));
+        let value = columns.next_back().and_then(|last| last.trim().parse::<f64>().ok());
+        if values.is_empty() && start.is_none() {
+            start = date;
+        }
+        match (start, date) {
+            (None, _) => values.push(value),
+            (Some(first), Some(date)) if date >= first => {
+                let day = date.daysSince(first) as usize;
+                if day >= values.len() {
+                    values.resize(day + 1, None);
+                }
+                values[day] = value;
+            },
+            (Some(_), _) => return Err(format!("line {} of {} has no date on or after the first one", i + 1, file_path).into())
+        }
+    }
+
+    Ok((start, values))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn windowsSliceTheObservedDays() {
+        let observed: Vec<Option<f64>> = vec![Some(1.0), Some(2.0), None, Some(4.0), Some(5.0)];
+        let simulated: Vec<f64> = (0..10).map(|d| d as f64 * 10.0).collect();
+
+        let (obs, sim) = DataAlignment::new(0, FitWindow::FirstN(4)).align(&observed, &simulated);
+        assert_eq!(obs, vec![1.0, 2.0, 4.0]);
+        assert_eq!(sim, vec![0.0, 10.0, 30.0]);
+
+        let (obs, sim) = DataAlignment::new(2, FitWindow::Range(1, 4)).align(&observed, &simulated);
+        assert_eq!(obs, vec![2.0, 4.0]);
+        assert_eq!(sim, vec![30.0, 50.0]);
+
+        // days past the end of the simulation are left out
+        let (obs, _) = DataAlignment::new(8, FitWindow::All).align(&observed, &simulated);
+        assert_eq!(obs, vec![1.0, 2.0]);
+    }
+
+    #[test]
+    fn horizonReachesTheLastDayInTheWindow() {
+        assert_eq!(DataAlignment::new(0, FitWindow::All).horizon(30), 30);
+        assert_eq!(DataAlignment::new(5, FitWindow::FirstN(10)).horizon(30), 15);
+        assert_eq!(DataAlignment::new(5, FitWindow::FirstN(100)).horizon(30), 35);
+        assert_eq!(DataAlignment::new(0, FitWindow::Range(20, 25)).horizon(30), 25);
+        assert_eq!(DataAlignment::new(0, FitWindow::All).horizon(0), 1);
+    }
+
+    #[test]
+    fn datesCountDays() {
+        let start = Date::parse("2020-02-27").unwrap();
+        assert_eq!(Date::parse("2020-03-01").unwrap().daysSince(start), 3);
+        assert_eq!(Date::parse("2021-03-01").unwrap().daysSince(Date::parse("2021-02-27").unwrap()), 2);
+        assert_eq!(Date::parse("1970-01-01").unwrap().daysSince(Date::new(1969, 12, 31).unwrap()), 1);
+        assert_eq!(Date::parse("2021-02-29"), None);
+        assert_eq!(Date::parse("12"), None);
+    }
+
+    #[test]
+    fn fromDatesConvertsToIndices() {
+        let simulationStart = Date::parse("2020-03-01").unwrap();
+        let dataStart = Date::parse("2020-03-05").unwrap();
+        let alignment = DataAlignment::fromDates(simulationStart, dataStart, Date::parse("2020-03-10").unwrap(), Date::parse("2020-03-12").unwrap());
+        assert_eq!(alignment, Some(DataAlignment::new(4, FitWindow::Range(5, 8))));
+        assert_eq!(DataAlignment::fromDates(dataStart, simulationStart, dataStart, dataStart), None);
+    }
+
+    #[test]
+    fn csvDatesPlaceTheValues() {
+        let path = std::env::temp_dir().join(format!("alignment-{}.csv", std::process::id()));
+        std::fs::write(&path, "date,cases\n2020-03-01,3\n2020-03-02,NA\n2020-03-04,7\n").unwrap();
+        let (start, values) = observationsFromCsv(path.to_str().unwrap(), true).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(start, Date::parse("2020-03-01"));
+        assert_eq!(values, vec![Some(3.0), None, None, Some(7.0)]);
+    }
+}
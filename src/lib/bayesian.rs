@@ -3,53 +3,96 @@ use std::sync::Arc;
 
 use nalgebra::{DVector, Dyn, DMatrix, Cholesky};
 use special::Error;
+use rand::{thread_rng,Rng};
 
-use super::sirmodel::SIRModel;
+use super::{sirmodel::{SIRModel, Observable}, wgpuInit::WgpuInit, parameter::{Parameter, fromUnitPoint}, kernel::Kernel, distributions::sampleNormal, loss::Loss, alignment::{DataAlignment, FitWindow, fromCounts}};
+
+// Mean function of a GaussianProcess, on the normalised inputs and standardised outputs
+pub type MeanFunction = Box<dyn Fn(&DVector<f64>) -> f64>;
 
 // Define a Gaussian Process struct
 pub struct GaussianProcess {
-    // Mean function
-    mean: Box<dyn Fn(f64) -> f64>,
+    mean: MeanFunction,
+
+    // Covariance function (kernel), on the normalised inputs
+    kernel: Box<dyn Kernel>,
 
-    // Covariance function (kernel)
-    kernel: Box<dyn Fn(f64, f64) -> f64>,
+    // Observation noise variance on the standardised outputs, also keeps the Cholesky stable
+    noise_variance: f64,
 
     // Training data
-    x_train: Vec<f64>,
+    x_train: Vec<DVector<f64>>,
     y_train: Vec<f64>,
+    // known noise variance of each observation, e.g. from replicate spread, on the scale of y
+    y_noise: Vec<f64>,
+
+    // Inputs are scaled to [0,1] between these bounds, taken from the training data unless set
+    x_bounds: Option<(DVector<f64>, DVector<f64>)>,
+    fixed_bounds: bool,
+
+    // Outputs are standardised to zero mean and unit variance
+    y_mean: f64,
+    y_std: f64,
 
     // Cholesky decomposition of the covariance matrix
     chol_decomp: Option<Cholesky<f64, Dyn>>,
+
+    // Kept from the last fit so predictions don't redo them: normalised training inputs and
+    // K^-1 (y - m(X))
+    x_norm: Vec<DVector<f64>>,
+    alpha: DVector<f64>,
 }
 
 impl GaussianProcess {
     // Constructor function to create a new Gaussian Process
-    pub fn new(mean: Box<dyn Fn(f64) -> f64>, kernel: Box<dyn Fn(f64, f64) -> f64>) -> Self {
+    pub fn new(mean: MeanFunction, kernel: Box<dyn Kernel>) -> Self {
         GaussianProcess {
             mean,
             kernel,
+            noise_variance: 1e-6,
             x_train: Vec::new(),
             y_train: Vec::new(),
+            y_noise: Vec::new(),
+            x_bounds: None,
+            fixed_bounds: false,
+            y_mean: 0.0,
+            y_std: 1.0,
             chol_decomp: None,
+            x_norm: Vec::new(),
+            alpha: DVector::zeros(0),
         }
     }
 
-    // Add training data to the GP
-    pub fn add_data(&mut self, x: f64, y: f64) {
-        let mut actualX = x;
-        let mut actualY = y;
-        if actualX < 0.0 {
-            actualX = -actualX;
+    // Fixed input bounds, for when the domain is known (e.g. the unit box)
+    pub fn set_input_bounds(&mut self, lower: DVector<f64>, upper: DVector<f64>) {
+        self.x_bounds = Some((lower, upper));
+        self.fixed_bounds = true;
+    }
+
+    fn normalise(&self, x: &DVector<f64>) -> DVector<f64> {
+        match &self.x_bounds {
+            Some((lower, upper)) => DVector::from_fn(x.len(), |i, _| {
+                let range = upper[i] - lower[i];
+                if range > 0.0 { (x[i] - lower[i]) / range } else { x[i] - lower[i] }
+            }),
+            None => x.clone()
         }
+    }
+
+    fn standardised_y(&self) -> Vec<f64> {
+        self.y_train.iter().map(|y| (y - self.y_mean) / self.y_std).collect()
+    }
 
-        //if actualY < 0.0 {
-        //    actualY = -actualY;
-        //}
-        self.x_train.push(actualX);
-        self.y_train.push(actualY);
-        println!("Added data x: {:?}",self.x_train);
-        println!("Added data y: {:?}",self.y_train);
-        
+    // Add training data to the GP
+    pub fn add_data(&mut self, x: DVector<f64>, y: f64) {
+        self.add_noisy_data(x, y, 0.0);
+    }
+
+    // Add an observation with a known noise variance, on top of the learned noise variance
+    pub fn add_noisy_data(&mut self, x: DVector<f64>, y: f64, noise: f64) {
+        self.x_train.push(x);
+        self.y_train.push(y);
+        self.y_noise.push(noise.max(0.0));
     }
 
     // Update the GP with training data
@@ -58,21 +101,25 @@ impl GaussianProcess {
         if self.x_train.is_empty() || self.y_train.is_empty() {
             panic!("Cannot fit without training data.");
         }
-        println!("This runs");
+        self.prepare();
 
         // Calculate the covariance matrix of the training data
+        let x_norm: Vec<DVector<f64>> = self.x_train.iter().map(|x| self.normalise(x)).collect();
         let cov_matrix = DMatrix::from_fn(self.x_train.len(), self.x_train.len(), |i, j| {
-            (self.kernel)(self.x_train[i], self.x_train[j])
+            self.kernel.eval(&x_norm[i], &x_norm[j])
         });
 
-        // Add a small diagonal jitter to improve numerical stability during Cholesky decomposition
-        let jitter = 1e-6;
-        let cov_matrix_jittered = cov_matrix + DMatrix::identity(self.x_train.len(), self.x_train.len()) * jitter;
+        // Add the noise variances to the diagonal
+        let cov_matrix_jittered = cov_matrix + self.noise_matrix();
 
         // Perform Cholesky decomposition
         match Cholesky::new(cov_matrix_jittered) {
             Some(chol_decomp) => {
+                let y = self.standardised_y();
+                let residuals = DVector::from_fn(self.x_train.len(), |i, _| y[i] - (self.mean)(&x_norm[i]));
+                self.alpha = chol_decomp.solve(&residuals);
                 self.chol_decomp = Some(chol_decomp);
+                self.x_norm = x_norm;
             }
             None => {
                 panic!("Cholesky decomposition failed. The covariance matrix may not be positive definite.");
@@ -80,6 +127,170 @@ impl GaussianProcess {
         }
     }
 
+    // Input bounds and output standardisation from the training data
+    fn prepare(&mut self) {
+        // Input bounds from the range of the training data
+        if !self.fixed_bounds {
+            let mut lower = self.x_train[0].clone();
+            let mut upper = self.x_train[0].clone();
+            for x in &self.x_train {
+                lower = lower.inf(x);
+                upper = upper.sup(x);
+            }
+            self.x_bounds = Some((lower, upper));
+        }
+
+        // Output mean and standard deviation, 1 while there is no spread
+        let n = self.y_train.len() as f64;
+        self.y_mean = self.y_train.iter().sum::<f64>() / n;
+        let var = self.y_train.iter().map(|y| (y - self.y_mean).powi(2)).sum::<f64>() / n;
+        self.y_std = if var > 0.0 { var.sqrt() } else { 1.0 };
+    }
+
+    // Learned noise variance plus each observation's own, on the standardised scale
+    fn noise_matrix(&self) -> DMatrix<f64> {
+        let n = self.x_train.len();
+        DMatrix::from_fn(n, n, |i, j| {
+            if i == j { self.noise_variance + self.y_noise[i] / (self.y_std * self.y_std) } else { 0.0 }
+        })
+    }
+
+    // Log hyperparameters as [kernel.., ln noise variance]
+    fn log_hyperparameters(&self) -> Vec<f64> {
+        let mut res = self.kernel.log_params();
+        res.push(self.noise_variance.ln());
+        res
+    }
+
+    fn set_log_hyperparameters(&mut self, params: &[f64]) {
+        let n = params.len() - 1;
+        self.kernel.set_log_params(&params[0..n]);
+        self.noise_variance = params[n].exp();
+    }
+
+    fn log_hyperparameter_bounds(&self) -> Vec<(f64, f64)> {
+        let mut res = self.kernel.log_bounds();
+        res.push(((1e-8f64).ln(), (10.0f64).ln()));
+        res
+    }
+
+    // Log marginal likelihood of the training data and its gradient with respect to the log
+    // hyperparameters, None when the covariance matrix is not positive definite
+    fn likelihood_and_gradient(&self) -> Option<(f64, Vec<f64>)> {
+        let n = self.x_train.len();
+        let x_norm: Vec<DVector<f64>> = self.x_train.iter().map(|x| self.normalise(x)).collect();
+        let y = self.standardised_y();
+        let residuals = DVector::from_fn(n, |i, _| y[i] - (self.mean)(&x_norm[i]));
+
+        let cov_matrix = DMatrix::from_fn(n, n, |i, j| self.kernel.eval(&x_norm[i], &x_norm[j]))
+            + self.noise_matrix();
+        let chol = Cholesky::new(cov_matrix)?;
+        let alpha = chol.solve(&residuals);
+        let log_det: f64 = chol.l().diagonal().iter().map(|d| d.ln()).sum::<f64>() * 2.0;
+        let likelihood = -0.5 * residuals.dot(&alpha) - 0.5 * log_det - 0.5 * (n as f64) * (2.0 * std::f64::consts::PI).ln();
+
+        // d/dtheta = 0.5 tr((alpha alpha^T - K^-1) dK/dtheta)
+        let inner = &alpha * alpha.transpose() - chol.inverse();
+        let num_kernel = self.kernel.log_params().len();
+        let mut gradient = vec![0.0; num_kernel + 1];
+        for i in 0..n {
+            for j in 0..n {
+                let dk = self.kernel.gradient(&x_norm[i], &x_norm[j]);
+                for p in 0..num_kernel {
+                    gradient[p] += 0.5 * inner[(i, j)] * dk[p];
+                }
+            }
+            gradient[num_kernel] += 0.5 * inner[(i, i)] * self.noise_variance;
+        }
+        Some((likelihood, gradient))
+    }
+
+    // Log marginal likelihood of the training data under the current hyperparameters
+    pub fn log_marginal_likelihood(&mut self) -> f64 {
+        self.prepare();
+        match self.likelihood_and_gradient() {
+            Some((likelihood, _)) => likelihood,
+            None => f64::NEG_INFINITY
+        }
+    }
+
+    // Fits the kernel hyperparameters and noise variance by maximising the log marginal likelihood
+    // with gradient ascent from the current values and starts - 1 random ones, then refits
+    pub fn learn_hyperparameters(&mut self, starts: usize, iterations: usize) -> f64 {
+        if self.x_train.is_empty() || self.y_train.is_empty() {
+            panic!("Cannot learn hyperparameters without training data.");
+        }
+        self.prepare();
+        let mut rng = thread_rng();
+        let bounds = self.log_hyperparameter_bounds();
+
+        let mut best_params = self.log_hyperparameters();
+        let mut best_likelihood = f64::NEG_INFINITY;
+        for start in 0..starts.max(1) {
+            let mut params = if start == 0 {
+                self.log_hyperparameters()
+            } else {
+                bounds.iter().map(|(lower, upper)| lower + rng.gen::<f64>() * (upper - lower)).collect()
+            };
+            self.set_log_hyperparameters(&params);
+            let (mut likelihood, mut gradient) = match self.likelihood_and_gradient() {
+                Some(res) => res,
+                None => continue
+            };
+
+            // gradient ascent, the step grows after a success and shrinks after a failure
+            let mut step = 0.1;
+            for _ in 0..iterations {
+                let norm = gradient.iter().map(|g| g * g).sum::<f64>().sqrt();
+                if norm < 1e-6 || step < 1e-8 {
+                    break;
+                }
+                let candidate: Vec<f64> = params.iter().zip(gradient.iter()).zip(bounds.iter())
+                    .map(|((p, g), (lower, upper))| (p + step * g / norm).max(*lower).min(*upper)).collect();
+                self.set_log_hyperparameters(&candidate);
+                match self.likelihood_and_gradient() {
+                    Some((new_likelihood, new_gradient)) if new_likelihood > likelihood => {
+                        params = candidate;
+                        likelihood = new_likelihood;
+                        gradient = new_gradient;
+                        step *= 1.2;
+                    },
+                    _ => {
+                        step *= 0.5;
+                    }
+                }
+            }
+
+            if likelihood > best_likelihood {
+                best_likelihood = likelihood;
+                best_params = params;
+            }
+        }
+
+        self.set_log_hyperparameters(&best_params);
+        self.fit();
+        best_likelihood
+    }
+
+    // Kernel hyperparameters by name
+    pub fn get_kernel_params(&self) -> Vec<(String, f64)> {
+        self.kernel.params()
+    }
+
+    pub fn get_noise_variance(&self) -> f64 {
+        self.noise_variance
+    }
+
+    // Drops the observations added after the first len, used to remove fantasy observations
+    pub fn truncate(&mut self, len: usize) {
+        if len < self.x_train.len() {
+            self.x_train.truncate(len);
+            self.y_train.truncate(len);
+            self.y_noise.truncate(len);
+            self.chol_decomp = None;
+        }
+    }
+
     pub fn checkEmpty(&mut self) -> bool {
         if self.x_train.is_empty() || self.y_train.is_empty() {
             return true;
@@ -88,40 +299,57 @@ impl GaussianProcess {
     }
 
     // Predict mean and variance at a given point
-    pub fn predict(&mut self, x: f64) -> (f64, f64) {
-
-
+    pub fn predict(&mut self, x: &DVector<f64>) -> (f64, f64) {
         // Check if there is no training data
         if self.x_train.is_empty() || self.y_train.is_empty() {
             panic!("Cannot predict without training data.");
         }
-        println!(";bdaskfjnbfn");
-        println!("{:?}",self.x_train);
-        println!("{:?}", self.y_train);
-
-        // Fit the GP if it hasn't been fitted yet
-        if self.x_train.len() != self.y_train.len() {
-            println!("fitting");
-            self.fit();
-        }
-        self.fit();
+        // Fit the GP if it hasn't been fitted since data was added
+        self.ensure_fitted();
+        let chol = self.chol_decomp.as_ref().unwrap();
 
-        // Calculate the mean prediction
-        let mean = (self.mean)(x);
+        let x = self.normalise(x);
 
-        // Calculate the covariance matrix between the training data and the test point
-        let cov_matrix = DMatrix::from_fn(self.x_train.len(), 1, |i, _| {
-            (self.kernel)(self.x_train[i], x)
+        // Calculate the covariance vector between the training data and the test point
+        let cov_vector = DVector::from_fn(self.x_norm.len(), |i, _| {
+            self.kernel.eval(&self.x_norm[i], &x)
         });
 
-        // Calculate the covariance matrix for the test point
-        let k_xx =  (self.kernel)(x, x);
+        // Calculate the mean prediction, the prior mean corrected by the residuals of the training data
+        let mean = (self.mean)(&x) + cov_vector.dot(&self.alpha);
 
+        // Calculate the predictive variance k(x,x) - v^T v with L v = k
+        let v = chol.l().solve_lower_triangular(&cov_vector).unwrap();
+        let variance = self.kernel.eval(&x, &x) - v.dot(&v);
 
-        // Calculate the predictive variance
-        let variance_matrix = (cov_matrix.transpose() * self.inverse_covariance_matrix() * cov_matrix).map(|value| k_xx - value);
-        let variance = variance_matrix.sum();
-        (mean, variance)
+        // Back to the scale of the outputs
+        (mean * self.y_std + self.y_mean, variance * self.y_std * self.y_std)
+    }
+
+    // Joint posterior mean and covariance at several points, for drawing correlated samples
+    pub fn predict_joint(&mut self, xs: &[DVector<f64>]) -> (DVector<f64>, DMatrix<f64>) {
+        if self.x_train.is_empty() || self.y_train.is_empty() {
+            panic!("Cannot predict without training data.");
+        }
+        self.ensure_fitted();
+        let chol = self.chol_decomp.as_ref().unwrap();
+
+        let xs: Vec<DVector<f64>> = xs.iter().map(|x| self.normalise(x)).collect();
+        let cross = DMatrix::from_fn(self.x_norm.len(), xs.len(), |i, j| self.kernel.eval(&self.x_norm[i], &xs[j]));
+        let prior = DMatrix::from_fn(xs.len(), xs.len(), |i, j| self.kernel.eval(&xs[i], &xs[j]));
+
+        let prior_mean = DVector::from_fn(xs.len(), |i, _| (self.mean)(&xs[i]));
+        let mean = prior_mean + cross.transpose() * &self.alpha;
+        let v = chol.l().solve_lower_triangular(&cross).unwrap();
+        let covariance = prior - v.transpose() * v;
+
+        (mean * self.y_std + DVector::from_element(xs.len(), self.y_mean), covariance * (self.y_std * self.y_std))
+    }
+
+    fn ensure_fitted(&mut self) {
+        if self.chol_decomp.is_none() || self.x_norm.len() != self.x_train.len() {
+            self.fit();
+        }
     }
 
     // Helper function to compute the inverse of the covariance matrix
@@ -134,201 +362,475 @@ impl GaussianProcess {
 
         // Solve the linear system using the Cholesky decomposition
         let identity_matrix = DMatrix::identity(self.x_train.len(), self.x_train.len());
-        let inverse_cov_matrix = chol_decomp.solve(&identity_matrix);
+        chol_decomp.solve(&identity_matrix)
+    }
+}
+
+// How the next point to evaluate is chosen
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum AcquisitionFunction {
+    ExpectedImprovement,
+    ProbabilityOfImprovement,
+    // mean - kappa * standard deviation
+    LowerConfidenceBound,
+    // minimum of a sample of the GP posterior
+    ThompsonSampling
+}
+
+// How the points of a batch after the first are chosen. Each chosen point is added to the GP with a
+// made up value so the next one is pulled elsewhere
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum BatchStrategy {
+    // the GP's own prediction at the point
+    KrigingBeliever,
+    // the best value observed so far
+    ConstantLiar
+}
+
+fn normal_cdf(z: f64) -> f64 {
+    0.5 * (1.0 + (z / std::f64::consts::SQRT_2).error())
+}
+
+fn normal_pdf(z: f64) -> f64 {
+    (-0.5 * z * z).exp() / (2.0 * std::f64::consts::PI).sqrt()
+}
+
+// n points in the unit box with exactly one point in each of n slices of every dimension
+fn latin_hypercube(n: usize, dims: usize) -> Vec<Vec<f64>> {
+    let mut rng = thread_rng();
+    let mut res = vec![vec![0.0; dims]; n];
+    for d in 0..dims {
+        let mut strata: Vec<usize> = (0..n).collect();
+        for i in (1..n).rev() {
+            strata.swap(i, rng.gen_range(0..=i));
+        }
+        for (point, stratum) in res.iter_mut().zip(&strata) {
+            point[d] = (*stratum as f64 + rng.gen::<f64>()) / (n as f64);
+        }
+    }
+    res
+}
 
-        inverse_cov_matrix
+// Minimises f over the unit box with the Nelder-Mead simplex, points are clamped into the box
+fn nelder_mead(f: &mut dyn FnMut(&Vec<f64>) -> f64, start: Vec<f64>, step: f64, iterations: usize) -> (Vec<f64>, f64) {
+    let dims = start.len();
+    let clamp = |x: Vec<f64>| -> Vec<f64> { x.into_iter().map(|v| v.clamp(0.0, 1.0)).collect() };
+
+    let mut simplex = vec![clamp(start.clone())];
+    for d in 0..dims {
+        let mut vertex = start.clone();
+        vertex[d] = if vertex[d] + step <= 1.0 { vertex[d] + step } else { vertex[d] - step };
+        simplex.push(clamp(vertex));
+    }
+    let mut values: Vec<f64> = simplex.iter().map(&mut *f).collect();
+
+    for _ in 0..iterations {
+        // order best to worst
+        let mut order: Vec<usize> = (0..simplex.len()).collect();
+        order.sort_by(|a, b| values[*a].partial_cmp(&values[*b]).unwrap_or(std::cmp::Ordering::Equal));
+        simplex = order.iter().map(|i| simplex[*i].clone()).collect();
+        values = order.iter().map(|i| values[*i]).collect();
+        if (values[dims] - values[0]).abs() < 1e-10 {
+            break;
+        }
+
+        let centroid: Vec<f64> = (0..dims).map(|d| simplex[0..dims].iter().map(|x| x[d]).sum::<f64>() / (dims as f64)).collect();
+        let towards = |coef: f64, simplex: &Vec<Vec<f64>>| -> Vec<f64> {
+            clamp((0..dims).map(|d| centroid[d] + coef * (simplex[dims][d] - centroid[d])).collect())
+        };
+
+        let reflected = towards(-1.0, &simplex);
+        let reflectedValue = f(&reflected);
+        if reflectedValue < values[0] {
+            let expanded = towards(-2.0, &simplex);
+            let expandedValue = f(&expanded);
+            if expandedValue < reflectedValue {
+                simplex[dims] = expanded;
+                values[dims] = expandedValue;
+            } else {
+                simplex[dims] = reflected;
+                values[dims] = reflectedValue;
+            }
+        } else if reflectedValue < values[dims - 1] {
+            simplex[dims] = reflected;
+            values[dims] = reflectedValue;
+        } else {
+            let contracted = towards(0.5, &simplex);
+            let contractedValue = f(&contracted);
+            if contractedValue < values[dims] {
+                simplex[dims] = contracted;
+                values[dims] = contractedValue;
+            } else {
+                // shrink everything towards the best point
+                for i in 1..simplex.len() {
+                    simplex[i] = (0..dims).map(|d| simplex[0][d] + 0.5 * (simplex[i][d] - simplex[0][d])).collect();
+                    values[i] = f(&simplex[i]);
+                }
+            }
+        }
+    }
+
+    let mut best = 0;
+    for i in 0..values.len() {
+        if values[i] < values[best] {
+            best = i;
+        }
     }
+    (simplex[best].clone(), values[best])
 }
 
 // Define a Bayesian Optimization struct
 pub struct BayesianOptimization {
     gp: GaussianProcess,
-    kappa: f64, // Exploration-exploitation trade-off parameter
+    kappa: f64, // Exploration-exploitation trade-off parameter, used by the lower confidence bound
+    acquisition: AcquisitionFunction,
     baseModel: Arc<std::sync::Mutex<SIRModel>>,
     expPopSize: usize,
-    simPopSize: usize,
+    observable: Observable,
+    // the GP works on the unit box, each axis mapped onto one of these
+    parameters: Vec<Parameter>,
+    // evaluated points of the unit box and their objective values
+    points: Vec<Vec<f64>>,
+    values: Vec<f64>,
+    // simulations per point, more are run while the mean is too uncertain to compare with the best
+    minReplicates: usize,
+    maxReplicates: usize,
+    // standard error of the mean, relative to its size, at which replicates stop
+    replicateTolerance: f64,
+    // points proposed per iteration, evaluated at the same time on separate copies of the model
+    batchSize: usize,
+    batchStrategy: BatchStrategy,
+    workers: usize,
+    loss: Loss,
+    // data compared with the simulations, the starting data when empty
+    observed: Vec<Option<f64>>,
+    alignment: DataAlignment,
 }
 
 impl BayesianOptimization {
     // Create a new Bayesian Optimization instance
-    pub fn new(gp: GaussianProcess, kappa:f64, baseModel: Arc<std::sync::Mutex<SIRModel>>,expPopSize: usize, simPopSize: usize) -> Self {
-        BayesianOptimization { gp, kappa, baseModel, expPopSize, simPopSize}
+    pub fn new(gp: GaussianProcess, kappa:f64, baseModel: Arc<std::sync::Mutex<SIRModel>>,expPopSize: usize, _simPopSize: usize) -> Self {
+        BayesianOptimization { gp, kappa, baseModel, acquisition: AcquisitionFunction::ExpectedImprovement, expPopSize, observable: Observable::Prevalence, parameters: vec![Parameter::new("spread", 0.0, 1.0)], points: Vec::new(), values: Vec::new(), minReplicates: 5, maxReplicates: 40, replicateTolerance: 0.05, batchSize: 1, batchStrategy: BatchStrategy::KrigingBeliever, workers: 1, loss: Loss::Rmse, observed: Vec::new(), alignment: DataAlignment::new(0, FitWindow::All)}
     }
 
-    // Optimize the objective function
-    pub async fn optimize(&mut self, num_iterations: usize, startingData: Vec<Vec<usize>>) -> f64 {
-        
-        let mut points = Vec::new();
-        for _ in 1..num_iterations {
-            println!("Does this run?");
-            
-            if self.gp.checkEmpty() {
-                let point = 0.01;
-                
-                let mut errorsSum = 0.0;
-                for i in 0..20 {
-                    let mut baseSimul = self.baseModel.lock().unwrap();
-                    println!("checking stuff {:?}",startingData[0].len() - 1);
-                    baseSimul.clearOut(startingData[0][0]);
-                    baseSimul.setSpread(point);
-                    baseSimul.setDays(40);
-                    baseSimul.runSim().await;
-                    let infected = baseSimul.propInfected();
-                    println!("Infected {:?}", infected);
-                    let currentError = Self::error(startingData[0].clone(),infected, self.expPopSize);
-                    errorsSum = errorsSum + currentError;
-                }   
-                let mut objective_value = (errorsSum / (100 as f64));
-                if objective_value < 0.0 {
-                    objective_value = -objective_value;
-                }
+    pub fn setReplicates(&mut self, minReplicates: usize, maxReplicates: usize, replicateTolerance: f64) {
+        self.minReplicates = minReplicates.max(2);
+        self.maxReplicates = maxReplicates.max(self.minReplicates);
+        self.replicateTolerance = replicateTolerance;
+    }
 
-                //println!("{:?}", infected);
-                //let objective_value = Self::error(startingData[0].clone(),infected);
-                //println!("{:?}", objective_value);
-                self.gp.add_data(point,objective_value);
-                //println!("this rasnds");
-            }
-            
-            // Select the next point to evaluate based on the acquisition function
-            let next_point = self.select_next_point();
-
-            let mut point = next_point;
-            println!("Point chosen: {:?}", point);
-            // Evaluate the objective function at the selected point
-            if next_point < 0.0 {
-                point = -point;
-            }
+    // Workers past the first each need their own GPU device and queue, there are fewer workers
+    // when not enough devices can be created
+    pub fn setBatch(&mut self, batchSize: usize, batchStrategy: BatchStrategy, workers: usize) {
+        self.batchSize = batchSize.max(1);
+        self.batchStrategy = batchStrategy;
+        self.workers = workers.max(1);
+    }
 
-            if next_point > 1.0 {
-                point = 1.0 / next_point
-            }
-            println!("checking stuff {:?}",point);
-            points.push(point);
-                    
-            let mut errorsSum = 0.0;
-            for i in 0..10 {
-                let mut baseSimul = self.baseModel.lock().unwrap();
-                baseSimul.clearOut(startingData[0][0]);
-                baseSimul.setSpread(point);
-                baseSimul.setDays(40);
-                baseSimul.runSim().await;
-                let infected = baseSimul.propInfected();
-                println!("Infected {:?}", infected);
-                let currentError = Self::error(startingData[0].clone(),infected, self.expPopSize);
-                errorsSum = errorsSum + currentError;
-            }   
-            let objective_value = (errorsSum / (10 as f64));
-
-
-            /*
-            let mut baseSimul = self.baseModel.lock().unwrap();
-            baseSimul.clearOut(startingData[0][0]);
-            baseSimul.setSpread(next_point);
-            baseSimul.setDays(startingData[0].len());
-            baseSimul.runSim().await;
-            let infected = baseSimul.numInfected();
-            let objective_value = Self::error(startingData[0].clone(),infected);
-            println!("{:?}",objective_value);
-            println!("Above isobjective value");
-            // Update the Gaussian Process with the new observation
-
-             */
-            self.gp.add_data(next_point, objective_value);
-            self.gp.fit();
-        }
-
-        println!("points: {:?}", points);
-        // Return the point with the best observed value
-        self.select_next_point()
+    pub fn setObservable(&mut self, observable: Observable) {
+        self.observable = observable;
     }
 
-    // Expected Improvement (EI) acquisition function
-    pub fn expected_improvement(&mut self, x: f64) -> f64 {
-        
-        
-        // Get the mean and variance predictions from the Gaussian Process
-        let (mean, variance) = self.gp.predict(x);
+    // Days can be missing, the simulated horizon is set from the data and the alignment
+    pub fn setObserved(&mut self, observed: Vec<Option<f64>>, alignment: DataAlignment) {
+        self.observed = observed;
+        self.alignment = alignment;
+    }
 
-        // Calculate the standard deviation (avoiding negative variance)
-        let std_dev = variance.max(0.0).sqrt();
+    pub fn setLoss(&mut self, loss: Loss) {
+        loss.validate();
+        self.loss = loss;
+    }
 
-        // Calculate the improvement over the current best observed value
-        let mut f_max = 0.0;
-        for y in self.gp.y_train.clone().into_iter() {
-            if y > f_max {
-                f_max = y;
+    pub fn setAcquisition(&mut self, acquisition: AcquisitionFunction) {
+        self.acquisition = acquisition;
+    }
+
+    // The kernel needs a length-scale per parameter if it has length-scales
+    pub fn setParameters(&mut self, parameters: Vec<Parameter>) {
+        if let Some(dims) = self.gp.kernel.input_dims() {
+            if dims != parameters.len() {
+                panic!("The kernel has {} length-scales but {} parameters are being fitted, give it one per parameter", dims, parameters.len());
             }
         }
-        let mut f_min = f_max;
-        for y in self.gp.y_train.clone().into_iter() {
-            if y < f_min {
-                f_min = y;
-            }
+        self.parameters = parameters;
+    }
+
+    // Optimize the objective function, returns the best evaluated parameter set
+    pub async fn optimize(&mut self, num_iterations: usize, startingData: Vec<Vec<usize>>) -> Vec<(String, f64)> {
+        let mut models = self.workerModels().await;
+        if self.observed.is_empty() {
+            self.observed = fromCounts(&startingData[0]);
         }
 
-        //println!("{:?}",self.gp.y_train.clone());
+        for name in self.unknownParameters(&mut models[0]) {
+            println!("Unknown parameter {:?} is not fitted", name);
+        }
 
+        if self.points.is_empty() {
+            // start in the middle of the box, which is also the answer with no iterations
+            let point = vec![0.5; self.parameters.len()];
+            let results = self.evaluateBatch(&mut models, std::slice::from_ref(&point), &startingData).await;
+            self.record(vec![point], results);
+        }
 
-        //let f_min = self.gp.y_train.min();
-        let improvement = mean - f_min;
+        for iteration in 1..=num_iterations {
+            // Select the next points to evaluate based on the acquisition function, or spread over
+            // the box while no point has a finite loss
+            let batch = if self.gp.checkEmpty() {
+                latin_hypercube(self.batchSize, self.parameters.len())
+            } else {
+                self.select_batch()
+            };
 
-        // Calculate the Z-score
-        let z_score = improvement / std_dev;
+            // Evaluate the objective function at the selected points
+            let results = self.evaluateBatch(&mut models, &batch, &startingData).await;
 
-        // Calculate the Expected Improvement
-        let ei = std_dev * (z_score * 0.5 * (1.0 + z_score.error())) + (-z_score * 0.5 * (1.0 - z_score.error()).exp());
+            // Update the Gaussian Process with the new observations
+            self.record(batch, results);
+            if self.gp.checkEmpty() {
+                println!("No simulated day lines up with the data, check the alignment");
+            } else if self.gp.y_train.len() >= 3 {
+                self.gp.learn_hyperparameters(5, 100);
+            } else {
+                self.gp.fit();
+            }
+            let best = self.best();
+            println!("Iteration {}: best loss {:?} at {:?}", iteration, self.values[best], fromUnitPoint(&self.parameters, &self.points[best]));
+        }
 
-        ei
+        // Return the point with the best observed value
+        let best = self.best();
+        fromUnitPoint(&self.parameters, &self.points[best])
     }
 
-    // Select the next point to evaluate based on the acquisition function (EI)
-    pub fn select_next_point(&mut self) -> f64 {
-        // Define a search space (replace with your actual search space)
-        let lower_bound = 0.0;
-        let upper_bound = 1.0;
+    // Index of the evaluated point with the lowest loss
+    fn best(&self) -> usize {
+        let mut best = 0;
+        for i in 0..self.values.len() {
+            if self.values[i] < self.values[best] {
+                best = i;
+            }
+        }
+        best
+    }
 
-        // Use a numerical optimization method to find the point that maximizes EI
-        let mut max_point = lower_bound;
-        let mut max_ei = f64::NEG_INFINITY;
+    // Names in the parameters the model has no parameter for. The others are set to the middle of
+    // their range, which every simulation overwrites
+    fn unknownParameters(&self, model: &mut SIRModel) -> Vec<String> {
+        self.parameters.iter().filter(|p| !model.setParam(&p.getName(), p.fromUnit(0.5))).map(|p| p.getName()).collect()
+    }
 
-        // Choose a set of candidate points for optimization (e.g., using a grid search)
-        let num_candidates = 100;
+    // Keeps the evaluated points. Infinite losses, from runs with no day to compare with the data,
+    // stay out of the GP
+    fn record(&mut self, batch: Vec<Vec<f64>>, results: Vec<(f64, f64)>) {
+        for (point, (objective_value, noise)) in batch.into_iter().zip(results) {
+            if objective_value.is_finite() {
+                self.gp.add_noisy_data(DVector::from_vec(point.clone()), objective_value, noise);
+            }
+            self.points.push(point);
+            self.values.push(objective_value);
+        }
+    }
 
-        let mut candidate_points = vec![lower_bound];
-        let spacing = (upper_bound - lower_bound) / ((num_candidates - 1 )as f64);
+    // Copies of the base model for the workers. The first shares the base model's GPU context
+    async fn workerModels(&mut self) -> Vec<SIRModel> {
+        let base = self.baseModel.lock().unwrap().clone();
+        let mut res = vec![base.clone()];
+        for _ in 1..self.workers {
+            // copies on the same device would only queue up behind each other
+            match WgpuInit::headless().await {
+                Some(wgpuinit) => {
+                    let mut model = base.clone();
+                    model.setWgpuInit(wgpuinit);
+                    res.push(model);
+                },
+                None => {
+                    println!("No extra GPU device available, running {:?} worker(s) instead of {:?}", res.len(), self.workers);
+                    break;
+                }
+            }
+        }
+        res
+    }
 
-        for i in 1..(num_candidates - 1) {
-            candidate_points.push(lower_bound + spacing * (i as f64));
+    // Evaluates the points. With one model they run in turn on this task, otherwise as many at a
+    // time as there are models, each on a blocking thread of the runtime
+    async fn evaluateBatch(&mut self, models: &mut Vec<SIRModel>, batch: &[Vec<f64>], startingData: &[Vec<usize>]) -> Vec<(f64, f64)> {
+        let best = self.values.iter().cloned().fold(f64::INFINITY, f64::min);
+        // the data counts real people, the models seed agents
+        let startInfNum = models[0].agentsFor(startingData[0][0] as f64, self.expPopSize);
+        let objective = Arc::new(Objective {parameters: self.parameters.clone(), observable: self.observable, loss: self.loss, observed: self.observed.clone(), alignment: self.alignment, startInfNum, expPopSize: self.expPopSize, minReplicates: self.minReplicates, maxReplicates: self.maxReplicates, replicateTolerance: self.replicateTolerance});
+
+        let mut res = Vec::new();
+        if models.len() == 1 {
+            for point in batch {
+                res.push(objective.evaluate(&mut models[0], point, best).await);
+            }
+            return res;
         }
 
-        
+        for chunk in batch.chunks(models.len()) {
+            let handles: Vec<_> = chunk.iter().cloned().zip(models.drain(..chunk.len()).collect::<Vec<SIRModel>>()).map(|(point, mut model)| {
+                let objective = objective.clone();
+                tokio::task::spawn_blocking(move || {
+                    let result = pollster::block_on(objective.evaluate(&mut model, &point, best));
+                    (model, result)
+                })
+            }).collect();
+            for handle in handles {
+                let (model, result) = handle.await.expect("Evaluation of a batch point panicked");
+                models.push(model);
+                res.push(result);
+            }
+        }
+        res
+    }
+
+    // Value of the pointwise acquisition functions at x, higher is more worth evaluating.
+    // The objective is minimised so improvement is below the best value observed so far
+    pub fn acquisition_value(&mut self, x: &DVector<f64>) -> f64 {
+        // Get the mean and variance predictions from the Gaussian Process
+        let (mean, variance) = self.gp.predict(x);
+
+        // Calculate the standard deviation (avoiding negative variance)
+        let std_dev = variance.max(0.0).sqrt();
+
+        let f_min = self.gp.y_train.iter().cloned().fold(f64::INFINITY, f64::min);
+        let improvement = f_min - mean;
 
-        for candidate in candidate_points {
-            let ei = self.expected_improvement(candidate);
-            //println!("Expected Improvement: {:?}", ei);
-            if ei > max_ei {
-                max_ei = ei;
-                max_point = candidate;
+        match self.acquisition {
+            AcquisitionFunction::ExpectedImprovement => {
+                if std_dev <= 1e-12 {
+                    return improvement.max(0.0);
+                }
+                let z_score = improvement / std_dev;
+                improvement * normal_cdf(z_score) + std_dev * normal_pdf(z_score)
+            },
+            AcquisitionFunction::ProbabilityOfImprovement => {
+                if std_dev <= 1e-12 {
+                    return if improvement > 0.0 { 1.0 } else { 0.0 };
+                }
+                normal_cdf(improvement / std_dev)
+            },
+            AcquisitionFunction::LowerConfidenceBound => -(mean - self.kappa * std_dev),
+            // drawn jointly over the candidates in select_next_point, a single draw here
+            AcquisitionFunction::ThompsonSampling => -(mean + std_dev * sampleNormal())
+        }
+    }
+
+    // Select the next point of the unit box to evaluate based on the acquisition function.
+    // Latin hypercube candidates, with the best few refined by Nelder-Mead
+    pub fn select_next_point(&mut self) -> Vec<f64> {
+        let dims = self.parameters.len();
+
+        // Choose a space-filling set of candidate points in the box, more for more parameters
+        let num_candidates = 100 * dims;
+        let candidates = latin_hypercube(num_candidates, dims);
+
+        if self.acquisition == AcquisitionFunction::ThompsonSampling {
+            // one sample of the whole posterior over the candidates, its minimum is the next point
+            let xs: Vec<DVector<f64>> = candidates.iter().map(|c| DVector::from_vec(c.clone())).collect();
+            let (mean, covariance) = self.gp.predict_joint(&xs);
+            let jitter = DMatrix::identity(xs.len(), xs.len()) * (1e-9 * self.gp.y_std * self.gp.y_std);
+            if let Some(chol) = Cholesky::new(covariance + jitter) {
+                let z = DVector::from_fn(xs.len(), |_, _| sampleNormal());
+                let sample = mean + chol.l() * z;
+                return candidates[sample.argmin().0].clone();
             }
         }
-        //println!("Does this run?");
 
-        if max_point < 0.0 {
-            max_point = - max_point;
+        let mut scored: Vec<(f64, Vec<f64>)> = candidates.into_iter().map(|c| (self.acquisition_value(&DVector::from_vec(c.clone())), c)).collect();
+        scored.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+
+        let mut best = scored[0].clone();
+        for (_, start) in scored.into_iter().take(5) {
+            let (point, negative) = nelder_mead(&mut |x: &Vec<f64>| -self.acquisition_value(&DVector::from_vec(x.clone())), start, 0.05, 100);
+            if -negative > best.0 {
+                best = (-negative, point);
+            }
         }
+        best.1
+    }
 
-        max_point
+    // Points for the next iteration. After each one the GP is given a fantasy observation there, and
+    // these are taken out again once the batch is complete
+    pub fn select_batch(&mut self) -> Vec<Vec<f64>> {
+        let observed = self.gp.y_train.len();
+        let mut batch = Vec::new();
+        for i in 0..self.batchSize {
+            let point = self.select_next_point();
+            if i + 1 < self.batchSize {
+                let x = DVector::from_vec(point.clone());
+                let fantasy = match self.batchStrategy {
+                    BatchStrategy::KrigingBeliever => self.gp.predict(&x).0,
+                    BatchStrategy::ConstantLiar => self.gp.y_train.iter().cloned().fold(f64::INFINITY, f64::min)
+                };
+                self.gp.add_noisy_data(x, fantasy, 0.0);
+                self.gp.fit();
+            }
+            batch.push(point);
+        }
+        self.gp.truncate(observed);
+        batch
     }
+}
 
-    
 
-    fn error(expected: Vec<usize>, simulated: Vec<f64>, expPopSize: usize) -> f64 {
-        let mut errsum = 0.0;
-        for n in 0..(simulated.len() - 1) {
-            errsum = errsum as f64 + (expected[n] as f64)/(expPopSize as f64) - simulated[n] as f64;
+// What one evaluation of a point runs, kept apart from the optimiser so worker threads can share it
+struct Objective {
+    parameters: Vec<Parameter>,
+    observable: Observable,
+    loss: Loss,
+    observed: Vec<Option<f64>>,
+    alignment: DataAlignment,
+    startInfNum: f64,
+    expPopSize: usize,
+    minReplicates: usize,
+    maxReplicates: usize,
+    replicateTolerance: f64
+}
+
+impl Objective {
+    // Mean loss of the model against the data over replicate runs at a point of the unit box, and
+    // the variance of that mean. Replicates stop once the mean is precise enough or clearly worse
+    // than the best point so far
+    async fn evaluate(&self, model: &mut SIRModel, point: &[f64], best: f64) -> (f64, f64) {
+        let mut losses = Vec::new();
+        loop {
+            let loss = self.simulate(model, point).await;
+            if !loss.is_finite() {
+                return (loss, 0.0);
+            }
+            losses.push(loss);
+            let runs = losses.len() as f64;
+            if losses.len() < self.minReplicates {
+                continue;
+            }
+            let mean = losses.iter().sum::<f64>() / runs;
+            let variance = losses.iter().map(|e| (e - mean).powi(2)).sum::<f64>() / (runs - 1.0);
+            let stdErr = (variance / runs).sqrt();
+            if losses.len() >= self.maxReplicates || stdErr <= self.replicateTolerance * mean.abs() || mean - 2.0 * stdErr > best {
+                return (mean, stdErr * stdErr);
+            }
         }
-        errsum / (simulated.len() as f64)
+    }
+
+    // Loss of one simulation at a point of the unit box
+    async fn simulate(&self, model: &mut SIRModel, point: &[f64]) -> f64 {
+        model.setParam("startInfNum", self.startInfNum);
+        // unknown names are reported once by optimize
+        for (name, value) in fromUnitPoint(&self.parameters, point) {
+            model.setParam(&name, value);
+        }
+        model.setDays(self.alignment.horizon(self.observed.len()));
+        model.reset();
+        model.runSim().await;
+        let infected = model.observe(self.observable);
+        // as counts in the observed population
+        let simulated: Vec<f64> = infected.iter().map(|p| p * self.expPopSize as f64).collect();
+        let (observed, simulated) = self.alignment.align(&self.observed, &simulated);
+        self.loss.evaluate(&observed, &simulated)
     }
 }
 
@@ -350,4 +852,36 @@ fn async main() {
 
     println!("Best Point: {}", best_point);
 }
- */
\ No newline at end of file
+ */
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latinHypercubeHasOnePointPerStratum() {
+        let n = 8;
+        let points = latin_hypercube(n, 3);
+        assert_eq!(points.len(), n);
+        for d in 0..3 {
+            let mut strata: Vec<usize> = points.iter().map(|p| (p[d] * n as f64).floor() as usize).collect();
+            strata.sort();
+            assert_eq!(strata, (0..n).collect::<Vec<usize>>());
+        }
+    }
+
+    #[test]
+    fn nelderMeadFindsTheMinimumOfAQuadratic() {
+        let mut quadratic = |x: &Vec<f64>| (x[0] - 0.3).powi(2) + 2.0 * (x[1] - 0.7).powi(2) + 1.0;
+        let (best, value) = nelder_mead(&mut quadratic, vec![0.9, 0.1], 0.1, 200);
+        assert!((best[0] - 0.3).abs() < 1e-3 && (best[1] - 0.7).abs() < 1e-3, "{:?}", best);
+        assert!((value - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn nelderMeadStaysInTheBox() {
+        // the unconstrained minimum is outside, at (1.5, -0.5)
+        let mut quadratic = |x: &Vec<f64>| (x[0] - 1.5).powi(2) + (x[1] + 0.5).powi(2);
+        let (best, _) = nelder_mead(&mut quadratic, vec![0.5, 0.5], 0.1, 200);
+        assert!((best[0] - 1.0).abs() < 1e-3 && best[1].abs() < 1e-3, "{:?}", best);
+    }
+}
@@ -31,10 +31,43 @@ fn doubler(num: f32) -> f32 {
 */
 
 
-@group(0) @binding(0) var<uniform> infRad: f32;
-@group(0) @binding(1) var<storage, read_write> inf: array<u32>;
+// infRad is the squared infection radius, swept is 1 to check the closest approach over the
+// substep instead of only the end positions
+struct ContactParams {
+    infRad: f32,
+    swept: f32,
+    pad1: f32,
+    pad2: f32,
+};
+@group(0) @binding(0) var<uniform> params: ContactParams;
+@group(0) @binding(1) var<storage, read> inf: array<u32>;
 @group(0) @binding(2) var<storage, read_write> xpos: array<f32>;
 @group(0) @binding(3) var<storage, read_write> ypos: array<f32>;
+// index + 1 of the infected agent that made contact with j, 0 if no contact
+@group(0) @binding(4) var<storage, read_write> infector: array<u32>;
+// 0 for empty slots in the population capacity
+@group(0) @binding(5) var<storage, read> alive: array<u32>;
+// positions at the start of the substep
+@group(0) @binding(6) var<storage, read> prev_xpos: array<f32>;
+@group(0) @binding(7) var<storage, read> prev_ypos: array<f32>;
+
+// squared distance between i and j, at the end of the substep or at their closest approach
+// when both moved in straight lines
+fn contactDistance(i: u32, j: u32) -> f32 {
+    let d1 = vec2<f32>(xpos[i] - xpos[j], ypos[i] - ypos[j]);
+    if params.swept < 0.5 {
+        return dot(d1, d1);
+    }
+    let d0 = vec2<f32>(prev_xpos[i] - prev_xpos[j], prev_ypos[i] - prev_ypos[j]);
+    let v = d1 - d0;
+    let vv = dot(v, v);
+    var t = 1.0;
+    if vv > 0.0 {
+        t = clamp(-dot(d0, v) / vv, 0.0, 1.0);
+    }
+    let d = d0 + t * v;
+    return dot(d, d);
+}
 
 /*
 @compute @workgroup_size(1,1) fn checkInf(
@@ -63,11 +96,13 @@ fn doubler(num: f32) -> f32 {
     let i: u32 = id.x;
     let j: u32 = id.y;
 
-    let dx = xpos[i] - xpos[j];
-    let dy = ypos[i] - ypos[j];
+    if alive[i] == 0u || alive[j] == 0u {
+        return;
+    }
 
     if inf[i] == 1u {
-        if inf[j] == 0u {
+        // removed agents can be reinfected by another strain, the CPU decides
+        if inf[j] != 1u {
             /*
             if xpos[i] > xpos[j] {
                 if dx < infRad {
@@ -121,14 +156,8 @@ fn doubler(num: f32) -> f32 {
                 }
             }
             */
-            if dx < infRad {
-                if dy < infRad {
-                    inf[j] = 1u;
-                } else {
-                    return;
-                }
-            } else {
-                return;
+            if contactDistance(i, j) < params.infRad {
+                infector[j] = i + 1u;
             }
         }
     }
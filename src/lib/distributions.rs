@@ -0,0 +1,134 @@
+use rand::{thread_rng,Rng};
+
+// Distributions used to draw per-agent values (infectiousness, delays, ...)
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Distribution {
+    Constant(f64),
+    Uniform(f64, f64),
+    // Gamma with shape k = dispersion and the given mean. Used as individual
+    // infectiousness this gives a negative-binomial offspring distribution with dispersion k
+    Gamma { mean: f64, dispersion: f64 },
+    // Log-normal parameterised by the mean and standard deviation of the value itself
+    LogNormal { mean: f64, sd: f64 }
+}
+
+impl Distribution {
+    pub fn sample(&self) -> f64 {
+        let mut rng = thread_rng();
+        match *self {
+            Distribution::Constant(value) => value,
+            Distribution::Uniform(min, max) => min + rng.gen::<f64>() * (max - min),
+            Distribution::Gamma { mean, dispersion } => {
+                if mean <= 0.0 || dispersion <= 0.0 {
+                    panic!("Gamma needs a positive mean and dispersion, got mean {} and dispersion {}", mean, dispersion);
+                }
+                sampleGamma(dispersion, mean / dispersion)
+            },
+            Distribution::LogNormal { mean, sd } => {
+                let sigmaSq = (1.0 + (sd * sd) / (mean * mean)).ln();
+                let mu = mean.ln() - sigmaSq / 2.0;
+                (mu + sigmaSq.sqrt() * sampleNormal()).exp()
+            }
+        }
+    }
+
+    pub fn mean(&self) -> f64 {
+        match *self {
+            Distribution::Constant(value) => value,
+            Distribution::Uniform(min, max) => (min + max) / 2.0,
+            Distribution::Gamma { mean, .. } => mean,
+            Distribution::LogNormal { mean, .. } => mean
+        }
+    }
+}
+
+// Standard normal sample using the Box-Muller transform
+pub fn sampleNormal() -> f64 {
+    let mut rng = thread_rng();
+    let u1 = 1.0 - rng.gen::<f64>();
+    let u2 = rng.gen::<f64>();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+}
+
+// Gamma sample using Marsaglia and Tsang's method
+pub fn sampleGamma(shape: f64, scale: f64) -> f64 {
+    let mut rng = thread_rng();
+    if shape < 1.0 {
+        // boost the shape above one and correct with a uniform power
+        let u = 1.0 - rng.gen::<f64>();
+        return sampleGamma(shape + 1.0, scale) * u.powf(1.0 / shape);
+    }
+    let d = shape - 1.0 / 3.0;
+    let c = 1.0 / (9.0 * d).sqrt();
+    loop {
+        let x = sampleNormal();
+        let v = (1.0 + c * x).powi(3);
+        if v <= 0.0 {
+            continue;
+        }
+        let u = 1.0 - rng.gen::<f64>();
+        if u.ln() < 0.5 * x * x + d - d * v + d * v.ln() {
+            return d * v * scale;
+        }
+    }
+}
+
+// Poisson sample, Knuth's method for small means and a rounded normal for large ones
+pub fn samplePoisson(lambda: f64) -> usize {
+    if lambda <= 0.0 {
+        return 0;
+    }
+    if lambda > 30.0 {
+        return (lambda + lambda.sqrt() * sampleNormal()).round().max(0.0) as usize;
+    }
+    let mut rng = thread_rng();
+    let limit = (-lambda).exp();
+    let mut k = 0;
+    let mut p = rng.gen::<f64>();
+    while p > limit {
+        k += 1;
+        p *= rng.gen::<f64>();
+    }
+    k
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sampleMean(draw: impl Fn() -> f64) -> f64 {
+        let n = 200000;
+        (0..n).map(|_| draw()).sum::<f64>() / n as f64
+    }
+
+    #[test]
+    fn sampleMeansMatch() {
+        let distributions = [
+            Distribution::Constant(2.5),
+            Distribution::Uniform(0.5, 1.5),
+            Distribution::Gamma { mean: 2.0, dispersion: 0.5 },
+            Distribution::Gamma { mean: 3.0, dispersion: 4.0 },
+            Distribution::LogNormal { mean: 1.5, sd: 0.8 }
+        ];
+        for d in distributions {
+            let mean = sampleMean(|| d.sample());
+            assert!((mean - d.mean()).abs() < 0.05 * d.mean(), "{:?}: {}", d, mean);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "positive mean and dispersion")]
+    fn gammaRejectsZeroDispersion() {
+        Distribution::Gamma { mean: 1.0, dispersion: 0.0 }.sample();
+    }
+
+    #[test]
+    fn poissonAndNormalMeans() {
+        assert!(sampleMean(sampleNormal).abs() < 0.02);
+        for lambda in [0.5, 4.0, 80.0] {
+            let mean = sampleMean(|| samplePoisson(lambda) as f64);
+            assert!((mean - lambda).abs() < 0.03 * lambda, "{}: {}", lambda, mean);
+        }
+        assert_eq!(samplePoisson(0.0), 0);
+    }
+}
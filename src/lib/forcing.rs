@@ -0,0 +1,61 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufRead};
+
+// Time-varying multiplier on the transmission probability
+#[derive(Clone, PartialEq, Debug)]
+pub enum Forcing {
+    None,
+    // 1 + amplitude * cos(2pi * (day / period - phase)), phase is a fraction of the period
+    Sinusoidal { amplitude: f64, phase: f64, period: f64 },
+    // (start day, multiplier) pairs in order of start day, 1 before the first one
+    Piecewise(Vec<(usize, f64)>),
+    // one multiplier per day, the last value is kept after the series ends
+    Series(Vec<f64>)
+}
+
+impl Forcing {
+    pub fn multiplier(&self, day: usize) -> f64 {
+        match self {
+            Forcing::None => 1.0,
+            Forcing::Sinusoidal { amplitude, phase, period } => {
+                let res = 1.0 + amplitude * (2.0 * std::f64::consts::PI * ((day as f64) / period - phase)).cos();
+                res.max(0.0)
+            },
+            Forcing::Piecewise(steps) => {
+                let mut res = 1.0;
+                for (start, value) in steps {
+                    if day >= *start {
+                        res = *value;
+                    }
+                }
+                res
+            },
+            Forcing::Series(values) => {
+                if values.is_empty() {
+                    1.0
+                } else if day < values.len() {
+                    values[day]
+                } else {
+                    values[values.len() - 1]
+                }
+            }
+        }
+    }
+
+    // Reads a per-day series with one multiplier per line
+    pub fn fromCsv(file_path: &str) -> Result<Forcing, Box<dyn Error>> {
+        let file = File::open(file_path)?;
+        let mut values = Vec::new();
+        let reader = io::BufReader::new(file);
+
+        for line in reader.lines() {
+            let line = line?;
+            if let Ok(value) = line.trim().trim_end_matches(',').parse::<f64>() {
+                values.push(value);
+            }
+        }
+
+        Ok(Forcing::Series(values))
+    }
+}
@@ -0,0 +1,74 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufRead};
+
+use super::distributions::samplePoisson;
+
+// Infections arriving from outside the simulated population
+#[derive(Clone, PartialEq, Debug)]
+pub enum Importation {
+    None,
+    // average imports per day, fractions carry over to later days
+    Constant(f64),
+    // exact number of imports on each day
+    Schedule(Vec<usize>),
+    // Poisson imports with mean perTraveller * volume[day]
+    Travel { perTraveller: f64, volume: Vec<f64> }
+}
+
+// Where imported infections are placed
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ImportPlacement {
+    Random,
+    At { x: f32, y: f32, radius: f32 }
+}
+
+impl Importation {
+    pub fn count(&self, day: usize) -> usize {
+        match self {
+            Importation::None => 0,
+            Importation::Constant(rate) => {
+                ((rate * (day + 1) as f64).floor() - (rate * day as f64).floor()) as usize
+            },
+            Importation::Schedule(counts) => {
+                if day < counts.len() {
+                    counts[day]
+                } else {
+                    0
+                }
+            },
+            Importation::Travel { perTraveller, volume } => {
+                if day < volume.len() {
+                    samplePoisson(perTraveller * volume[day])
+                } else {
+                    0
+                }
+            }
+        }
+    }
+
+    pub fn scheduleFromCsv(file_path: &str) -> Result<Importation, Box<dyn Error>> {
+        let counts = readColumn(file_path)?.into_iter().map(|v| v.round().max(0.0) as usize).collect();
+        Ok(Importation::Schedule(counts))
+    }
+
+    pub fn travelFromCsv(file_path: &str, perTraveller: f64) -> Result<Importation, Box<dyn Error>> {
+        let volume = readColumn(file_path)?;
+        Ok(Importation::Travel { perTraveller, volume })
+    }
+}
+
+fn readColumn(file_path: &str) -> Result<Vec<f64>, Box<dyn Error>> {
+    let file = File::open(file_path)?;
+    let mut values = Vec::new();
+    let reader = io::BufReader::new(file);
+
+    for line in reader.lines() {
+        let line = line?;
+        if let Ok(value) = line.trim().trim_end_matches(',').parse::<f64>() {
+            values.push(value);
+        }
+    }
+
+    Ok(values)
+}
@@ -0,0 +1,46 @@
+// Infectiousness by day since infection (the generation-interval kernel)
+#[derive(Clone, PartialEq, Debug)]
+pub enum InfectiousnessProfile {
+    Flat,
+    // relative weight for day 1, 2, ... of the infection, days past the end are not infectious
+    Discrete(Vec<f64>),
+    Gamma { shape: f64, scale: f64 },
+    Weibull { shape: f64, scale: f64 }
+}
+
+impl InfectiousnessProfile {
+    // Weight for each day 1..=infectiousPeriod, scaled so the mean over the infectious period is 1
+    // and the spread rate keeps meaning the average per-contact transmission probability
+    pub fn weights(&self, infectiousPeriod: usize) -> Vec<f64> {
+        let mut res = Vec::new();
+        for day in 1..=infectiousPeriod {
+            // evaluate continuous kernels at the middle of the day
+            let t = day as f64 - 0.5;
+            let weight = match self {
+                InfectiousnessProfile::Flat => 1.0,
+                InfectiousnessProfile::Discrete(values) => {
+                    if day <= values.len() {
+                        values[day - 1]
+                    } else {
+                        0.0
+                    }
+                },
+                InfectiousnessProfile::Gamma { shape, scale } => {
+                    t.powf(shape - 1.0) * (-t / scale).exp()
+                },
+                InfectiousnessProfile::Weibull { shape, scale } => {
+                    t.powf(shape - 1.0) * (-(t / scale).powf(*shape)).exp()
+                }
+            };
+            res.push(weight);
+        }
+
+        let total: f64 = res.iter().sum();
+        if total > 0.0 {
+            for w in res.iter_mut() {
+                *w = *w * (infectiousPeriod as f64) / total;
+            }
+        }
+        res
+    }
+}
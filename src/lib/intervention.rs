@@ -1,3 +1,5 @@
+use super::venue::VenueCategory;
+
 #[derive(PartialEq, Clone, Copy)]
 pub struct Intervention {
     intType: InterventionType,
@@ -8,8 +10,7 @@ pub struct Intervention {
 
 impl Intervention {
     pub fn new(inter: InterventionType, startTime: usize, endTime: usize) -> Intervention {
-        let res = Intervention {intType: inter, startTime, endTime, active: false};
-        res
+        Intervention {intType: inter, startTime, endTime, active: false}
     }
 
     pub fn setAct(&mut self, active: bool) {
@@ -32,5 +33,7 @@ impl Intervention {
 #[derive(PartialEq, Clone, Copy)]
 pub enum InterventionType {
     Kkkkkzone,
-    Mask
+    Mask,
+    // no visits to venues of this category while active
+    CloseVenues(VenueCategory)
 }
\ No newline at end of file
@@ -0,0 +1,574 @@
+use nalgebra::DVector;
+
+// Covariance function of a GaussianProcess. Hyperparameters are handled in log space so they stay
+// positive while being learned
+pub trait Kernel {
+    fn eval(&self, x1: &DVector<f64>, x2: &DVector<f64>) -> f64;
+
+    fn log_params(&self) -> Vec<f64>;
+
+    fn set_log_params(&mut self, params: &[f64]);
+
+    // Range of sensible log hyperparameters on normalised inputs and standardised outputs
+    fn log_bounds(&self) -> Vec<(f64, f64)>;
+
+    // Derivative of the kernel with respect to each log hyperparameter
+    fn gradient(&self, x1: &DVector<f64>, x2: &DVector<f64>) -> Vec<f64>;
+
+    fn param_names(&self) -> Vec<String>;
+
+    // Number of inputs the kernel has length-scales for, None if it takes any number
+    fn input_dims(&self) -> Option<usize>;
+
+    fn box_clone(&self) -> Box<dyn Kernel>;
+
+    // Hyperparameters with their names, for printing and saving
+    fn params(&self) -> Vec<(String, f64)> {
+        self.param_names().into_iter().zip(self.log_params().into_iter().map(|p| p.exp())).collect()
+    }
+
+    // Sets the hyperparameters from values in the order of params
+    fn set_params(&mut self, values: &[f64]) {
+        let logs: Vec<f64> = values.iter().map(|v| v.ln()).collect();
+        self.set_log_params(&logs);
+    }
+}
+
+impl Clone for Box<dyn Kernel> {
+    fn clone(&self) -> Box<dyn Kernel> {
+        self.box_clone()
+    }
+}
+
+fn lengthScaleBounds(dims: usize) -> Vec<(f64, f64)> {
+    vec![((1e-3f64).ln(), (1e2f64).ln()); dims]
+}
+
+fn varianceBounds() -> (f64, f64) {
+    ((1e-3f64).ln(), (1e3f64).ln())
+}
+
+fn lengthScaleNames(dims: usize) -> Vec<String> {
+    (0..dims).map(|i| format!("length_scale_{}", i)).collect()
+}
+
+// Both sides of a Sum or Product see the same inputs
+fn checkSameDims(left: &dyn Kernel, right: &dyn Kernel) {
+    if let (Some(l), Some(r)) = (left.input_dims(), right.input_dims()) {
+        if l != r {
+            panic!("Cannot combine kernels with {} and {} length-scales, they need one per input", l, r);
+        }
+    }
+}
+
+// Squared distance scaled by the length-scales and each dimension's part of it
+fn scaledDistance(length_scales: &[f64], x1: &DVector<f64>, x2: &DVector<f64>) -> (f64, Vec<f64>) {
+    let parts: Vec<f64> = (0..x1.len()).map(|i| ((x1[i] - x2[i]) / length_scales[i]).powi(2)).collect();
+    (parts.iter().sum(), parts)
+}
+
+// Squared exponential kernel with one length-scale per input dimension (ARD)
+#[derive(Clone, PartialEq, Debug)]
+pub struct SquaredExponential {
+    length_scales: Vec<f64>,
+    variance: f64
+}
+
+impl SquaredExponential {
+    pub fn new(length_scales: Vec<f64>, variance: f64) -> SquaredExponential {
+        SquaredExponential { length_scales, variance }
+    }
+}
+
+impl Kernel for SquaredExponential {
+    fn eval(&self, x1: &DVector<f64>, x2: &DVector<f64>) -> f64 {
+        let (dist, _) = scaledDistance(&self.length_scales, x1, x2);
+        self.variance * (-0.5 * dist).exp()
+    }
+
+    fn log_params(&self) -> Vec<f64> {
+        let mut res: Vec<f64> = self.length_scales.iter().map(|l| l.ln()).collect();
+        res.push(self.variance.ln());
+        res
+    }
+
+    fn set_log_params(&mut self, params: &[f64]) {
+        for (l, p) in self.length_scales.iter_mut().zip(params) {
+            *l = p.exp();
+        }
+        self.variance = params[self.length_scales.len()].exp();
+    }
+
+    fn log_bounds(&self) -> Vec<(f64, f64)> {
+        let mut res = lengthScaleBounds(self.length_scales.len());
+        res.push(varianceBounds());
+        res
+    }
+
+    fn gradient(&self, x1: &DVector<f64>, x2: &DVector<f64>) -> Vec<f64> {
+        let k = self.eval(x1, x2);
+        let (_, parts) = scaledDistance(&self.length_scales, x1, x2);
+        let mut res: Vec<f64> = parts.iter().map(|p| k * p).collect();
+        res.push(k);
+        res
+    }
+
+    fn param_names(&self) -> Vec<String> {
+        let mut res = lengthScaleNames(self.length_scales.len());
+        res.push(String::from("variance"));
+        res
+    }
+
+    fn input_dims(&self) -> Option<usize> {
+        Some(self.length_scales.len())
+    }
+
+    fn box_clone(&self) -> Box<dyn Kernel> {
+        Box::new(self.clone())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Smoothness {
+    Half,
+    ThreeHalves,
+    FiveHalves
+}
+
+// Matern kernel with ARD length-scales. Half is the exponential kernel, FiveHalves is close to the
+// squared exponential but less smooth
+#[derive(Clone, PartialEq, Debug)]
+pub struct Matern {
+    smoothness: Smoothness,
+    length_scales: Vec<f64>,
+    variance: f64
+}
+
+impl Matern {
+    pub fn new(smoothness: Smoothness, length_scales: Vec<f64>, variance: f64) -> Matern {
+        Matern { smoothness, length_scales, variance }
+    }
+}
+
+impl Kernel for Matern {
+    fn eval(&self, x1: &DVector<f64>, x2: &DVector<f64>) -> f64 {
+        let (dist, _) = scaledDistance(&self.length_scales, x1, x2);
+        let r = dist.sqrt();
+        match self.smoothness {
+            Smoothness::Half => self.variance * (-r).exp(),
+            Smoothness::ThreeHalves => {
+                let s = 3f64.sqrt() * r;
+                self.variance * (1.0 + s) * (-s).exp()
+            },
+            Smoothness::FiveHalves => {
+                let s = 5f64.sqrt() * r;
+                self.variance * (1.0 + s + s * s / 3.0) * (-s).exp()
+            }
+        }
+    }
+
+    fn log_params(&self) -> Vec<f64> {
+        let mut res: Vec<f64> = self.length_scales.iter().map(|l| l.ln()).collect();
+        res.push(self.variance.ln());
+        res
+    }
+
+    fn set_log_params(&mut self, params: &[f64]) {
+        for (l, p) in self.length_scales.iter_mut().zip(params) {
+            *l = p.exp();
+        }
+        self.variance = params[self.length_scales.len()].exp();
+    }
+
+    fn log_bounds(&self) -> Vec<(f64, f64)> {
+        let mut res = lengthScaleBounds(self.length_scales.len());
+        res.push(varianceBounds());
+        res
+    }
+
+    fn gradient(&self, x1: &DVector<f64>, x2: &DVector<f64>) -> Vec<f64> {
+        let k = self.eval(x1, x2);
+        let (dist, parts) = scaledDistance(&self.length_scales, x1, x2);
+        let r = dist.sqrt();
+        // dk/dln l_i = factor * (d_i / l_i)^2
+        let factor = match self.smoothness {
+            Smoothness::Half => if r > 0.0 { k / r } else { 0.0 },
+            Smoothness::ThreeHalves => 3.0 * self.variance * (-(3f64.sqrt()) * r).exp(),
+            Smoothness::FiveHalves => {
+                let s = 5f64.sqrt() * r;
+                5.0 / 3.0 * self.variance * (1.0 + s) * (-s).exp()
+            }
+        };
+        let mut res: Vec<f64> = parts.iter().map(|p| factor * p).collect();
+        res.push(k);
+        res
+    }
+
+    fn param_names(&self) -> Vec<String> {
+        let mut res = lengthScaleNames(self.length_scales.len());
+        res.push(String::from("variance"));
+        res
+    }
+
+    fn input_dims(&self) -> Option<usize> {
+        Some(self.length_scales.len())
+    }
+
+    fn box_clone(&self) -> Box<dyn Kernel> {
+        Box::new(self.clone())
+    }
+}
+
+// Rational quadratic kernel, a mixture of squared exponentials over length-scales. Large alpha
+// tends to the squared exponential
+#[derive(Clone, PartialEq, Debug)]
+pub struct RationalQuadratic {
+    length_scales: Vec<f64>,
+    alpha: f64,
+    variance: f64
+}
+
+impl RationalQuadratic {
+    pub fn new(length_scales: Vec<f64>, alpha: f64, variance: f64) -> RationalQuadratic {
+        RationalQuadratic { length_scales, alpha, variance }
+    }
+}
+
+impl Kernel for RationalQuadratic {
+    fn eval(&self, x1: &DVector<f64>, x2: &DVector<f64>) -> f64 {
+        let (dist, _) = scaledDistance(&self.length_scales, x1, x2);
+        self.variance * (1.0 + dist / (2.0 * self.alpha)).powf(-self.alpha)
+    }
+
+    fn log_params(&self) -> Vec<f64> {
+        let mut res: Vec<f64> = self.length_scales.iter().map(|l| l.ln()).collect();
+        res.push(self.alpha.ln());
+        res.push(self.variance.ln());
+        res
+    }
+
+    fn set_log_params(&mut self, params: &[f64]) {
+        let n = self.length_scales.len();
+        for (l, p) in self.length_scales.iter_mut().zip(params) {
+            *l = p.exp();
+        }
+        self.alpha = params[n].exp();
+        self.variance = params[n + 1].exp();
+    }
+
+    fn log_bounds(&self) -> Vec<(f64, f64)> {
+        let mut res = lengthScaleBounds(self.length_scales.len());
+        res.push(((1e-2f64).ln(), (1e2f64).ln()));
+        res.push(varianceBounds());
+        res
+    }
+
+    fn gradient(&self, x1: &DVector<f64>, x2: &DVector<f64>) -> Vec<f64> {
+        let k = self.eval(x1, x2);
+        let (dist, parts) = scaledDistance(&self.length_scales, x1, x2);
+        let base = 1.0 + dist / (2.0 * self.alpha);
+        let factor = self.variance * base.powf(-self.alpha - 1.0);
+        let mut res: Vec<f64> = parts.iter().map(|p| factor * p).collect();
+        res.push(k * self.alpha * (-base.ln() + dist / (2.0 * self.alpha * base)));
+        res.push(k);
+        res
+    }
+
+    fn param_names(&self) -> Vec<String> {
+        let mut res = lengthScaleNames(self.length_scales.len());
+        res.push(String::from("alpha"));
+        res.push(String::from("variance"));
+        res
+    }
+
+    fn input_dims(&self) -> Option<usize> {
+        Some(self.length_scales.len())
+    }
+
+    fn box_clone(&self) -> Box<dyn Kernel> {
+        Box::new(self.clone())
+    }
+}
+
+// Periodic kernel with the same period and length-scale in every dimension
+#[derive(Clone, PartialEq, Debug)]
+pub struct Periodic {
+    length_scale: f64,
+    period: f64,
+    variance: f64
+}
+
+impl Periodic {
+    pub fn new(length_scale: f64, period: f64, variance: f64) -> Periodic {
+        Periodic { length_scale, period, variance }
+    }
+}
+
+impl Kernel for Periodic {
+    fn eval(&self, x1: &DVector<f64>, x2: &DVector<f64>) -> f64 {
+        let mut sum = 0.0;
+        for i in 0..x1.len() {
+            sum += (std::f64::consts::PI * (x1[i] - x2[i]) / self.period).sin().powi(2);
+        }
+        self.variance * (-2.0 * sum / self.length_scale.powi(2)).exp()
+    }
+
+    fn log_params(&self) -> Vec<f64> {
+        vec![self.length_scale.ln(), self.period.ln(), self.variance.ln()]
+    }
+
+    fn set_log_params(&mut self, params: &[f64]) {
+        self.length_scale = params[0].exp();
+        self.period = params[1].exp();
+        self.variance = params[2].exp();
+    }
+
+    fn log_bounds(&self) -> Vec<(f64, f64)> {
+        vec![((1e-3f64).ln(), (1e2f64).ln()), ((1e-2f64).ln(), (1e2f64).ln()), varianceBounds()]
+    }
+
+    fn gradient(&self, x1: &DVector<f64>, x2: &DVector<f64>) -> Vec<f64> {
+        let k = self.eval(x1, x2);
+        let l2 = self.length_scale.powi(2);
+        let mut sum = 0.0;
+        let mut periodSum = 0.0;
+        for i in 0..x1.len() {
+            let angle = std::f64::consts::PI * (x1[i] - x2[i]) / self.period;
+            sum += angle.sin().powi(2);
+            periodSum += angle.sin() * angle.cos() * angle;
+        }
+        vec![k * 4.0 * sum / l2, k * 4.0 * periodSum / l2, k]
+    }
+
+    fn param_names(&self) -> Vec<String> {
+        vec![String::from("length_scale"), String::from("period"), String::from("variance")]
+    }
+
+    fn input_dims(&self) -> Option<usize> {
+        None
+    }
+
+    fn box_clone(&self) -> Box<dyn Kernel> {
+        Box::new(self.clone())
+    }
+}
+
+// Linear kernel bias + variance * x1.x2, a GP with it is Bayesian linear regression
+#[derive(Clone, PartialEq, Debug)]
+pub struct Linear {
+    bias: f64,
+    variance: f64
+}
+
+impl Linear {
+    pub fn new(bias: f64, variance: f64) -> Linear {
+        Linear { bias, variance }
+    }
+}
+
+impl Kernel for Linear {
+    fn eval(&self, x1: &DVector<f64>, x2: &DVector<f64>) -> f64 {
+        self.bias + self.variance * x1.dot(x2)
+    }
+
+    fn log_params(&self) -> Vec<f64> {
+        vec![self.bias.ln(), self.variance.ln()]
+    }
+
+    fn set_log_params(&mut self, params: &[f64]) {
+        self.bias = params[0].exp();
+        self.variance = params[1].exp();
+    }
+
+    fn log_bounds(&self) -> Vec<(f64, f64)> {
+        vec![((1e-6f64).ln(), (1e2f64).ln()), varianceBounds()]
+    }
+
+    fn gradient(&self, x1: &DVector<f64>, x2: &DVector<f64>) -> Vec<f64> {
+        vec![self.bias, self.variance * x1.dot(x2)]
+    }
+
+    fn param_names(&self) -> Vec<String> {
+        vec![String::from("bias"), String::from("variance")]
+    }
+
+    fn input_dims(&self) -> Option<usize> {
+        None
+    }
+
+    fn box_clone(&self) -> Box<dyn Kernel> {
+        Box::new(self.clone())
+    }
+}
+
+// Sum of two kernels, hyperparameters are the left's followed by the right's
+#[derive(Clone)]
+pub struct Sum {
+    left: Box<dyn Kernel>,
+    right: Box<dyn Kernel>
+}
+
+impl Sum {
+    pub fn new(left: Box<dyn Kernel>, right: Box<dyn Kernel>) -> Sum {
+        checkSameDims(left.as_ref(), right.as_ref());
+        Sum { left, right }
+    }
+}
+
+impl Kernel for Sum {
+    fn eval(&self, x1: &DVector<f64>, x2: &DVector<f64>) -> f64 {
+        self.left.eval(x1, x2) + self.right.eval(x1, x2)
+    }
+
+    fn log_params(&self) -> Vec<f64> {
+        let mut res = self.left.log_params();
+        res.extend(self.right.log_params());
+        res
+    }
+
+    fn set_log_params(&mut self, params: &[f64]) {
+        let n = self.left.log_params().len();
+        self.left.set_log_params(&params[0..n]);
+        self.right.set_log_params(&params[n..]);
+    }
+
+    fn log_bounds(&self) -> Vec<(f64, f64)> {
+        let mut res = self.left.log_bounds();
+        res.extend(self.right.log_bounds());
+        res
+    }
+
+    fn gradient(&self, x1: &DVector<f64>, x2: &DVector<f64>) -> Vec<f64> {
+        let mut res = self.left.gradient(x1, x2);
+        res.extend(self.right.gradient(x1, x2));
+        res
+    }
+
+    fn param_names(&self) -> Vec<String> {
+        let mut res: Vec<String> = self.left.param_names().into_iter().map(|n| format!("left.{}", n)).collect();
+        res.extend(self.right.param_names().into_iter().map(|n| format!("right.{}", n)));
+        res
+    }
+
+    fn input_dims(&self) -> Option<usize> {
+        self.left.input_dims().or(self.right.input_dims())
+    }
+
+    fn box_clone(&self) -> Box<dyn Kernel> {
+        Box::new(self.clone())
+    }
+}
+
+// Product of two kernels, hyperparameters are the left's followed by the right's
+#[derive(Clone)]
+pub struct Product {
+    left: Box<dyn Kernel>,
+    right: Box<dyn Kernel>
+}
+
+impl Product {
+    pub fn new(left: Box<dyn Kernel>, right: Box<dyn Kernel>) -> Product {
+        checkSameDims(left.as_ref(), right.as_ref());
+        Product { left, right }
+    }
+}
+
+impl Kernel for Product {
+    fn eval(&self, x1: &DVector<f64>, x2: &DVector<f64>) -> f64 {
+        self.left.eval(x1, x2) * self.right.eval(x1, x2)
+    }
+
+    fn log_params(&self) -> Vec<f64> {
+        let mut res = self.left.log_params();
+        res.extend(self.right.log_params());
+        res
+    }
+
+    fn set_log_params(&mut self, params: &[f64]) {
+        let n = self.left.log_params().len();
+        self.left.set_log_params(&params[0..n]);
+        self.right.set_log_params(&params[n..]);
+    }
+
+    fn log_bounds(&self) -> Vec<(f64, f64)> {
+        let mut res = self.left.log_bounds();
+        res.extend(self.right.log_bounds());
+        res
+    }
+
+    fn gradient(&self, x1: &DVector<f64>, x2: &DVector<f64>) -> Vec<f64> {
+        let leftValue = self.left.eval(x1, x2);
+        let rightValue = self.right.eval(x1, x2);
+        let mut res: Vec<f64> = self.left.gradient(x1, x2).into_iter().map(|g| g * rightValue).collect();
+        res.extend(self.right.gradient(x1, x2).into_iter().map(|g| g * leftValue));
+        res
+    }
+
+    fn param_names(&self) -> Vec<String> {
+        let mut res: Vec<String> = self.left.param_names().into_iter().map(|n| format!("left.{}", n)).collect();
+        res.extend(self.right.param_names().into_iter().map(|n| format!("right.{}", n)));
+        res
+    }
+
+    fn input_dims(&self) -> Option<usize> {
+        self.left.input_dims().or(self.right.input_dims())
+    }
+
+    fn box_clone(&self) -> Box<dyn Kernel> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Compares each gradient entry with a central difference in the log hyperparameter
+    fn checkGradient(kernel: &dyn Kernel) {
+        let x1 = DVector::from_vec(vec![0.3, 0.7]);
+        let x2 = DVector::from_vec(vec![0.55, 0.2]);
+        let gradient = kernel.gradient(&x1, &x2);
+        let params = kernel.log_params();
+        assert_eq!(gradient.len(), params.len());
+        let h = 1e-6;
+        for i in 0..params.len() {
+            let mut shifted = kernel.box_clone();
+            let mut up = params.clone();
+            up[i] += h;
+            shifted.set_log_params(&up);
+            let upper = shifted.eval(&x1, &x2);
+            let mut down = params.clone();
+            down[i] -= h;
+            shifted.set_log_params(&down);
+            let lower = shifted.eval(&x1, &x2);
+            let numeric = (upper - lower) / (2.0 * h);
+            assert!((gradient[i] - numeric).abs() < 1e-6 * (1.0 + numeric.abs()), "{}: {} against {}", kernel.param_names()[i], gradient[i], numeric);
+        }
+    }
+
+    #[test]
+    fn gradientsMatchFiniteDifferences() {
+        checkGradient(&SquaredExponential::new(vec![0.4, 0.9], 1.3));
+        for smoothness in [Smoothness::Half, Smoothness::ThreeHalves, Smoothness::FiveHalves] {
+            checkGradient(&Matern::new(smoothness, vec![0.4, 0.9], 1.3));
+        }
+        checkGradient(&RationalQuadratic::new(vec![0.4, 0.9], 2.0, 1.3));
+        checkGradient(&Periodic::new(0.8, 1.7, 1.3));
+        checkGradient(&Linear::new(0.5, 1.3));
+        checkGradient(&Sum::new(Box::new(SquaredExponential::new(vec![0.4, 0.9], 1.3)), Box::new(Linear::new(0.5, 1.3))));
+        checkGradient(&Product::new(Box::new(Matern::new(Smoothness::FiveHalves, vec![0.4, 0.9], 1.3)), Box::new(Periodic::new(0.8, 1.7, 1.3))));
+    }
+
+    #[test]
+    fn inputDimsComeFromTheLengthScales() {
+        assert_eq!(SquaredExponential::new(vec![0.2; 3], 1.0).input_dims(), Some(3));
+        assert_eq!(Linear::new(1.0, 1.0).input_dims(), None);
+        assert_eq!(Sum::new(Box::new(Linear::new(1.0, 1.0)), Box::new(Matern::new(Smoothness::Half, vec![0.2; 2], 1.0))).input_dims(), Some(2));
+    }
+
+    #[test]
+    #[should_panic]
+    fn combiningDifferentDimsFails() {
+        Product::new(Box::new(SquaredExponential::new(vec![0.2; 3], 1.0)), Box::new(SquaredExponential::new(vec![0.2; 2], 1.0)));
+    }
+}
@@ -0,0 +1,107 @@
+use special::Gamma;
+
+// How far a simulated daily series is from the data, lower is better. Both series are counts in the
+// observed population, compared day by day over the days both have. All are averages over the
+// days so the scale does not depend on the length of the fit window
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Loss {
+    Rmse,
+    Mae,
+    // rmse of log(1 + count), for series spanning orders of magnitude
+    LogRmse,
+    // negative log likelihood per day of the data with the simulated counts as the daily means
+    Poisson,
+    // as Poisson with variance mean + mean^2 / dispersion, for overdispersed data
+    NegativeBinomial(f64)
+}
+
+impl Loss {
+    // Panics on a loss that cannot be evaluated, before any simulation is run for it
+    pub fn validate(&self) {
+        if let Loss::NegativeBinomial(dispersion) = *self {
+            if dispersion <= 0.0 || dispersion.is_nan() {
+                panic!("NegativeBinomial needs a positive dispersion, got {}", dispersion);
+            }
+        }
+    }
+
+    // Infinite when there are no days to compare, so a window that misses the data is never the best fit
+    pub fn evaluate(&self, observed: &[f64], simulated: &[f64]) -> f64 {
+        let pairs: Vec<(f64, f64)> = observed.iter().cloned().zip(simulated.iter().cloned()).collect();
+        if pairs.is_empty() {
+            return f64::INFINITY;
+        }
+        let n = pairs.len() as f64;
+        match self {
+            Loss::Rmse => (pairs.iter().map(|(y, mu)| (y - mu).powi(2)).sum::<f64>() / n).sqrt(),
+            Loss::Mae => pairs.iter().map(|(y, mu)| (y - mu).abs()).sum::<f64>() / n,
+            Loss::LogRmse => (pairs.iter().map(|(y, mu)| (y.max(0.0).ln_1p() - mu.max(0.0).ln_1p()).powi(2)).sum::<f64>() / n).sqrt(),
+            Loss::Poisson => pairs.iter().map(|(y, mu)| {
+                let mu = floorMean(*mu);
+                mu - y * mu.ln() + lnFactorial(*y)
+            }).sum::<f64>() / n,
+            Loss::NegativeBinomial(dispersion) => pairs.iter().map(|(y, mu)| {
+                let (mu, k) = (floorMean(*mu), *dispersion);
+                -(Gamma::ln_gamma(y + k).0 - Gamma::ln_gamma(k).0 - lnFactorial(*y) + k * (k / (k + mu)).ln() + y * (mu / (k + mu)).ln())
+            }).sum::<f64>() / n
+        }
+    }
+}
+
+// Days the simulation has no cases on would otherwise make any observed case infinitely unlikely
+fn floorMean(mu: f64) -> f64 {
+    mu.max(0.1)
+}
+
+fn lnFactorial(y: f64) -> f64 {
+    Gamma::ln_gamma(y.max(0.0) + 1.0).0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matchesHandComputedValues() {
+        let observed = vec![3.0, 0.0, 7.0];
+        let simulated = vec![2.5, 0.0, 8.0];
+        assert!((Loss::Rmse.evaluate(&observed, &simulated) - (1.25f64 / 3.0).sqrt()).abs() < 1e-12);
+        assert!((Loss::Mae.evaluate(&observed, &simulated) - 0.5).abs() < 1e-12);
+        let logRmse = (((4.0f64 / 3.5).ln().powi(2) + (8.0f64 / 9.0).ln().powi(2)) / 3.0).sqrt();
+        assert!((Loss::LogRmse.evaluate(&observed, &simulated) - logRmse).abs() < 1e-12);
+
+        // mu - y ln mu + ln y!, with the empty day's mean floored at 0.1
+        let poisson = ((2.5 - 3.0 * 2.5f64.ln() + 6.0f64.ln()) + 0.1 + (8.0 - 7.0 * 8.0f64.ln() + 5040.0f64.ln())) / 3.0;
+        assert!((Loss::Poisson.evaluate(&observed, &simulated) - poisson).abs() < 1e-9);
+    }
+
+    #[test]
+    fn negativeBinomialTendsToPoisson() {
+        let observed = vec![3.0, 0.0, 7.0];
+        let simulated = vec![2.5, 0.0, 8.0];
+        let poisson = Loss::Poisson.evaluate(&observed, &simulated);
+        assert!((Loss::NegativeBinomial(1e7).evaluate(&observed, &simulated) - poisson).abs() < 1e-5);
+        // overdispersion makes the far day less unlikely but the close days more so
+        assert!(Loss::NegativeBinomial(1.0).evaluate(&observed, &simulated) > poisson);
+    }
+
+    #[test]
+    #[should_panic(expected = "positive dispersion")]
+    fn negativeBinomialRejectsZeroDispersion() {
+        Loss::NegativeBinomial(0.0).validate();
+    }
+
+    #[test]
+    fn isAMeanOverDays() {
+        let loss = Loss::Poisson;
+        let once = loss.evaluate(&[4.0], &[2.0]);
+        assert!((loss.evaluate(&[4.0; 10], &[2.0; 10]) - once).abs() < 1e-12);
+    }
+
+    #[test]
+    fn emptyWindowIsNeverAGoodFit() {
+        for loss in [Loss::Rmse, Loss::Mae, Loss::LogRmse, Loss::Poisson, Loss::NegativeBinomial(2.0)] {
+            assert_eq!(loss.evaluate(&Vec::new(), &[1.0, 2.0]), f64::INFINITY);
+        }
+    }
+}
@@ -117,7 +117,7 @@ impl Matrix {
         Matrix::from((self.data)
         .clone()
         .into_iter()
-        .map(|row| row.into_iter().map(|value| function(value)).collect()).collect())
+        .map(|row| row.into_iter().map(function).collect()).collect())
     }
 
     pub fn transpose (&mut self) -> Matrix {
@@ -6,4 +6,18 @@ pub mod sirmodel;
 pub mod wgpuInit;
 pub mod intervention;
 pub mod trainer;
-pub mod bayesian;
\ No newline at end of file
+pub mod bayesian;
+pub mod distributions;
+pub mod infectiousness;
+pub mod strain;
+pub mod forcing;
+pub mod importation;
+pub mod spawn;
+pub mod movement;
+pub mod venue;
+pub mod rt;
+pub mod transmissiontree;
+pub mod parameter;
+pub mod kernel;
+pub mod loss;
+pub mod alignment;
\ No newline at end of file
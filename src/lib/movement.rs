@@ -0,0 +1,59 @@
+// Movement kernels in moveshader.wgsl
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MovementModel {
+    Straight,
+    RandomWaypoint,
+    CorrelatedWalk,
+    LevyFlight,
+    HomeAnchored
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Movement {
+    model: MovementModel,
+    // standard deviation of the turning angle per step in radians
+    turnSd: f32,
+    // Pareto exponent and minimum length of Levy flight steps
+    levyAlpha: f32,
+    levyMinStep: f32,
+    // chance per day of heading back home, and how far from home people wander
+    homeReturnProb: f32,
+    homeRadius: f32
+}
+
+impl Movement {
+    pub fn new(model: MovementModel) -> Movement {
+        Movement {model, turnSd: 0.5, levyAlpha: 1.5, levyMinStep: 10.0, homeReturnProb: 0.1, homeRadius: 2000.0}
+    }
+
+    pub fn getModel(&mut self) -> MovementModel {
+        self.model
+    }
+
+    // Sets a movement parameter by name so mobility can be calibrated, false for unknown names
+    pub fn setParam(&mut self, name: &str, value: f64) -> bool {
+        match name {
+            "turnSd" => self.turnSd = value as f32,
+            "levyAlpha" => self.levyAlpha = value as f32,
+            "levyMinStep" => self.levyMinStep = value as f32,
+            "homeReturnProb" => self.homeReturnProb = value as f32,
+            "homeRadius" => self.homeRadius = value as f32,
+            _ => return false
+        }
+        true
+    }
+
+    // Contents of the MoveParams uniform, dt is the fraction of a day each move covers and
+    // speedScale the lockdown factor in force, which Levy flight steps need as they redraw the velocity
+    pub fn uniform(&self, seed: u32, dt: f32, speedScale: f32) -> [f32;9] {
+        let model = match self.model {
+            MovementModel::Straight => 0.0,
+            MovementModel::RandomWaypoint => 1.0,
+            MovementModel::CorrelatedWalk => 2.0,
+            MovementModel::LevyFlight => 3.0,
+            MovementModel::HomeAnchored => 4.0
+        };
+        // the seed goes through an f32 so keep it exact
+        [model, (seed % 16777216) as f32, self.turnSd, self.levyAlpha, self.levyMinStep, self.homeReturnProb, self.homeRadius, dt, speedScale]
+    }
+}
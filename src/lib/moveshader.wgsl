@@ -36,12 +36,54 @@ fn doubler(num: f32) -> f32 {
 @group(0) @binding(3) var<storage, read_write> y_pos: array<f32>;
 @group(0) @binding(4) var<storage, read_write> x_vel: array<f32>;
 @group(0) @binding(5) var<storage, read_write> y_vel: array<f32>;
+// 0 for empty slots in the population capacity
+@group(0) @binding(6) var<storage, read> alive: array<u32>;
+// x and y pairs, kept in one buffer each to stay within eight storage buffers
+@group(0) @binding(7) var<storage, read> homes: array<vec2<f32>>;
+@group(0) @binding(8) var<storage, read_write> waypoints: array<vec2<f32>>;
 
-@compute @workgroup_size(1,1,1) fn movePosChange(
-    @builtin(global_invocation_id) id: vec3<u32>
-) {
-    let i: u32 = id.x;
-    let pos: vec2<f32> = vec2<f32>(x_pos[i] + x_vel[i], y_pos[i]+y_vel[i]);
+// see movement.rs, model is 0 straight line, 1 random waypoint, 2 correlated walk,
+// 3 levy flight, 4 home anchored
+struct MoveParams {
+    model: f32,
+    seed: f32,
+    turnSd: f32,
+    levyAlpha: f32,
+    levyMinStep: f32,
+    homeReturnProb: f32,
+    homeRadius: f32,
+    // fraction of a day covered by this substep
+    dt: f32,
+    // lockdown factor in force
+    speedScale: f32,
+};
+@group(0) @binding(9) var<uniform> params: MoveParams;
+
+const PI: f32 = 3.14159265;
+
+var<private> rngState: u32;
+
+fn pcgHash(v: u32) -> u32 {
+    let state = v * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// uniform in [0, 1)
+fn rand() -> f32 {
+    rngState = pcgHash(rngState);
+    return f32(rngState >> 8u) / 16777216.0;
+}
+
+fn randNormal() -> f32 {
+    let u1 = 1.0 - rand();
+    let u2 = rand();
+    return sqrt(-2.0 * log(u1)) * cos(2.0 * PI * u2);
+}
+
+// move by the velocity, bouncing off the arena walls
+fn step(i: u32) {
+    let pos: vec2<f32> = vec2<f32>(x_pos[i] + x_vel[i] * params.dt, y_pos[i]+y_vel[i] * params.dt);
 
     if pos[0] < 0.0 {
         x_pos[i] = -pos[0];
@@ -56,10 +98,108 @@ fn doubler(num: f32) -> f32 {
     if pos[1] < 0.0 {
         y_pos[i] = -pos[1];
         y_vel[i] = - y_vel[i];
-    } else if pos[1] > x_edge {
+    } else if pos[1] > y_edge {
         y_pos[i] = 2.0 * y_edge - pos[1];
         y_vel[i] = - y_vel[i];
     } else {
         y_pos[i] = pos[1];
     }
 }
+
+fn turn(i: u32, angle: f32) {
+    let c = cos(angle);
+    let s = sin(angle);
+    let vx = x_vel[i];
+    let vy = y_vel[i];
+    x_vel[i] = c * vx - s * vy;
+    y_vel[i] = s * vx + c * vy;
+}
+
+fn headTowards(i: u32, goal: vec2<f32>) {
+    let speed = length(vec2<f32>(x_vel[i], y_vel[i]));
+    let to = goal - vec2<f32>(x_pos[i], y_pos[i]);
+    let dist = length(to);
+    if dist > 0.0 {
+        x_vel[i] = to.x / dist * speed;
+        y_vel[i] = to.y / dist * speed;
+    }
+}
+
+fn randomWaypoint(i: u32) {
+    let speed = length(vec2<f32>(x_vel[i], y_vel[i]));
+    let goal = waypoints[i];
+    if distance(goal, vec2<f32>(x_pos[i], y_pos[i])) <= speed * params.dt {
+        x_pos[i] = goal.x;
+        y_pos[i] = goal.y;
+        waypoints[i] = vec2<f32>(rand() * x_edge, rand() * y_edge);
+        headTowards(i, waypoints[i]);
+    } else {
+        headTowards(i, goal);
+        step(i);
+    }
+}
+
+fn correlatedWalk(i: u32) {
+    turn(i, params.turnSd * sqrt(params.dt) * randNormal());
+    step(i);
+}
+
+fn levyFlight(i: u32) {
+    let angle = 2.0 * PI * rand();
+    let u = max(1.0 - rand(), 0.000001);
+    let len = min(params.levyMinStep * pow(u, -1.0 / params.levyAlpha), max(x_edge, y_edge)) * params.speedScale;
+    x_vel[i] = cos(angle) * len;
+    y_vel[i] = sin(angle) * len;
+    step(i);
+}
+
+// walks near home, and now and then heads back there at its usual speed. The waypoint is set
+// to the home while heading back
+fn homeAnchored(i: u32) {
+    let home = homes[i];
+    var returning = all(waypoints[i] == home);
+    if !returning && rand() < params.homeReturnProb * params.dt {
+        waypoints[i] = home;
+        returning = true;
+    }
+    if returning {
+        let speed = length(vec2<f32>(x_vel[i], y_vel[i]));
+        if distance(home, vec2<f32>(x_pos[i], y_pos[i])) <= speed * params.dt {
+            x_pos[i] = home.x;
+            y_pos[i] = home.y;
+            waypoints[i] = vec2<f32>(-1.0, -1.0);
+        } else {
+            headTowards(i, home);
+            step(i);
+        }
+        return;
+    }
+    turn(i, params.turnSd * sqrt(params.dt) * randNormal());
+    if distance(home, vec2<f32>(x_pos[i], y_pos[i])) > params.homeRadius {
+        headTowards(i, home);
+    }
+    step(i);
+}
+
+@compute @workgroup_size(1,1,1) fn movePosChange(
+    @builtin(global_invocation_id) id: vec3<u32>
+) {
+    let i: u32 = id.x;
+    if alive[i] == 0u {
+        return;
+    }
+    rngState = pcgHash(i ^ pcgHash(u32(params.seed)));
+
+    let model = u32(params.model);
+    if model == 1u {
+        randomWaypoint(i);
+    } else if model == 2u {
+        correlatedWalk(i);
+    } else if model == 3u {
+        levyFlight(i);
+    } else if model == 4u {
+        homeAnchored(i);
+    } else {
+        step(i);
+    }
+}
@@ -1,4 +1,4 @@
-use super::{matrix::Matrix, activations::{Activation, self}};
+use super::{matrix::Matrix, activations::Activation};
 
 pub struct Network<'a> {
     layers: Vec<usize>,
@@ -10,7 +10,7 @@ pub struct Network<'a> {
 }
 
 impl Network<'_> {
-    pub fn new<'a>(layers: Vec<usize>, learning_rate: f64, activation: Activation<'a>) -> Network {
+    pub fn new<'a>(layers: Vec<usize>, learning_rate: f64, activation: Activation<'a>) -> Network<'a> {
         let mut weights = vec![];
         let mut biases = vec![];
 
@@ -0,0 +1,76 @@
+// A model parameter being calibrated, see SIRModel::setParam for the names
+#[derive(Clone, PartialEq, Debug)]
+pub struct Parameter {
+    name: String,
+    lower: f64,
+    upper: f64,
+    // search evenly in log space, for parameters spanning orders of magnitude
+    logScale: bool
+}
+
+impl Parameter {
+    pub fn new(name: &str, lower: f64, upper: f64) -> Parameter {
+        Parameter {name: String::from(name), lower, upper, logScale: false}
+    }
+
+    // Bounds have to be positive
+    pub fn logScaled(name: &str, lower: f64, upper: f64) -> Parameter {
+        Parameter {name: String::from(name), lower, upper, logScale: true}
+    }
+
+    pub fn getName(&self) -> String {
+        self.name.clone()
+    }
+
+    pub fn getBounds(&self) -> (f64, f64) {
+        (self.lower, self.upper)
+    }
+
+    // Position of value inside the bounds, 0 at lower and 1 at upper
+    pub fn toUnit(&self, value: f64) -> f64 {
+        let res = if self.logScale {
+            (value.ln() - self.lower.ln()) / (self.upper.ln() - self.lower.ln())
+        } else {
+            (value - self.lower) / (self.upper - self.lower)
+        };
+        res.clamp(0.0, 1.0)
+    }
+
+    pub fn fromUnit(&self, unit: f64) -> f64 {
+        let unit = unit.clamp(0.0, 1.0);
+        if self.logScale {
+            (self.lower.ln() + unit * (self.upper.ln() - self.lower.ln())).exp()
+        } else {
+            self.lower + unit * (self.upper - self.lower)
+        }
+    }
+}
+
+// Parameter values for a point of the unit box, paired with their names
+pub fn fromUnitPoint(parameters: &[Parameter], point: &[f64]) -> Vec<(String, f64)> {
+    parameters.iter().zip(point.iter()).map(|(p, u)| (p.getName(), p.fromUnit(*u))).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn logScaleRoundTrips() {
+        let p = Parameter::logScaled("startInfNum", 1.0, 100.0);
+        assert!((p.fromUnit(0.5) - 10.0).abs() < 1e-9);
+        for value in [1.0, 3.7, 10.0, 99.0] {
+            assert!((p.fromUnit(p.toUnit(value)) - value).abs() < 1e-9 * value);
+        }
+        let linear = Parameter::new("spread", 0.0, 2.0);
+        assert!((linear.fromUnit(linear.toUnit(0.3)) - 0.3).abs() < 1e-12);
+    }
+
+    #[test]
+    fn valuesOutsideTheBoundsAreClamped() {
+        let p = Parameter::logScaled("startInfNum", 1.0, 100.0);
+        assert_eq!(p.toUnit(1000.0), 1.0);
+        assert_eq!(p.toUnit(0.5), 0.0);
+        assert_eq!(p.fromUnit(-0.2), 1.0);
+    }
+}
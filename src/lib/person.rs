@@ -1,4 +1,4 @@
-use super::matrix::Matrix;
+use super::{matrix::Matrix, distributions::Distribution};
 use rand::{thread_rng,Rng};
 
 #[derive(Clone)]
@@ -16,11 +16,10 @@ pub struct Person {
 }
 
 impl Person {
-    pub fn random(state: Personstate, spreadMinMax: Matrix, mut spawnLoc: Matrix, mut velocityMinMax: Matrix) -> Person {
-        let mut spreadChance = Matrix::random(2,1).multiply(&spreadMinMax).data[0][0];
-        if spreadChance > 1.0 {
-            spreadChance = 1.0;
-        } else if spreadChance < 0.0 {
+    pub fn random(state: Personstate, infectiousness: &Distribution, mut spawnLoc: Matrix, mut velocityMinMax: Matrix) -> Person {
+        // relative infectiousness, multiplies the model's spread rate when this person infects someone
+        let mut spreadChance = infectiousness.sample();
+        if spreadChance < 0.0 {
             spreadChance = 0.0;
         }
 
@@ -36,16 +35,20 @@ impl Person {
         } else if num * 0.25 < randNum && randNum < num * 0.5 {
              loc = Locations::SE;
         } else if num * 0.5 < randNum && randNum < num * 0.75 {
-            loc = Locations::SE;
+            loc = Locations::SW;
         } else if num * 0.75 < randNum && randNum < num {
-            loc = Locations::SE;
+            loc = Locations::NW;
         }
 
 
-        Person {state, spreadChance, posx: (rand.gen::<f32>() * (spawnLoc.get(0,0) as f32)), posy: (rand.gen::<f32>() * (spawnLoc.get(0,1) as f32)), velx: (rand.gen::<f32>() * (velocityMinMax.get(0,0) as f32)), vely: (rand.gen::<f32>() * (velocityMinMax.get(0,1) as f32)), daysInfected: 0,is_from: loc, chance_to_city: randNum * num2 ,in_city: true}
+        // speed between the min and max velocity in a random direction
+        let speed = velocityMinMax.get(0,0) as f32 + rand.gen::<f32>() * ((velocityMinMax.get(0,1) - velocityMinMax.get(0,0)) as f32);
+        let angle = rand.gen::<f32>() * 2.0 * std::f32::consts::PI;
+
+        Person {state, spreadChance, posx: (rand.gen::<f32>() * (spawnLoc.get(0,0) as f32)), posy: (rand.gen::<f32>() * (spawnLoc.get(0,1) as f32)), velx: speed * angle.cos(), vely: speed * angle.sin(), daysInfected: 0,is_from: loc, chance_to_city: randNum * num2 ,in_city: true}
     }
 
-    pub fn changeState(&mut self, day:usize, state: Personstate) {
+    pub fn changeState(&mut self, _day:usize, state: Personstate) {
         self.state = state;
     }
 
@@ -70,6 +73,14 @@ impl Person {
         self.in_city
     }
 
+    pub fn getIsFrom(&mut self) -> Locations {
+        self.is_from
+    }
+
+    pub fn getChanceToCity(&mut self) -> f64 {
+        self.chance_to_city
+    }
+
     pub fn getDaysInfected(&mut self) -> usize {
         self.daysInfected
     }
@@ -80,11 +91,12 @@ impl Person {
 
     pub fn infect(&mut self) -> bool {
         let mut rand = thread_rng();
-        if rand.gen::<f64>() *2.0 - 1.0 < self.spreadChance{
-            false
-        } else {
-            true 
-        }
+        rand.gen::<f64>() *2.0 - 1.0 >= self.spreadChance
+    }
+
+    pub fn setPos(&mut self, posx: f32, posy: f32) {
+        self.posx = posx;
+        self.posy = posy;
     }
 
     pub fn getPosVel(&mut self) -> [f32;4] {
@@ -0,0 +1,129 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufRead};
+
+// Posterior of R_t for one day, a gamma distribution summarised by its mean, sd and 95% interval
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RtEstimate {
+    pub day: usize,
+    pub mean: f64,
+    pub sd: f64,
+    pub lower: f64,
+    pub upper: f64
+}
+
+// Cori et al. (2013) renewal-equation estimator, R_t is assumed constant over a trailing window.
+// The incidence can come from a simulation or from case data
+#[derive(Clone, PartialEq, Debug)]
+pub struct CoriEstimator {
+    // probability of a serial interval of s days, index 0 is a same-day interval
+    serialInterval: Vec<f64>,
+    window: usize,
+    priorMean: f64,
+    priorSd: f64
+}
+
+impl CoriEstimator {
+    pub fn new(serialInterval: Vec<f64>, window: usize) -> CoriEstimator {
+        let total: f64 = serialInterval.iter().sum();
+        let serialInterval = if total > 0.0 { serialInterval.iter().map(|w| w / total).collect() } else { serialInterval };
+        CoriEstimator {serialInterval, window: window.max(1), priorMean: 5.0, priorSd: 5.0}
+    }
+
+    // Gamma prior on R_t, the default mean and sd of 5 are the usual vague choice
+    pub fn setPrior(&mut self, priorMean: f64, priorSd: f64) {
+        self.priorMean = priorMean;
+        self.priorSd = priorSd;
+    }
+
+    // Serial interval from a gamma distribution with the given mean and sd, cut off after maxDays
+    pub fn gammaSerialInterval(mean: f64, sd: f64, maxDays: usize) -> Vec<f64> {
+        let shape = (mean / sd).powi(2);
+        let scale = sd * sd / mean;
+        let mut res = vec![0.0];
+        for day in 1..=maxDays {
+            let t = day as f64;
+            res.push(t.powf(shape - 1.0) * (-t / scale).exp());
+        }
+        res
+    }
+
+    // Estimates for every day with a full window behind it. local are the cases caused inside the
+    // population, imported ones can infect others but were not caused by it (empty for none)
+    pub fn estimate(&self, local: &[f64], imported: &[f64]) -> Vec<RtEstimate> {
+        let total: Vec<f64> = (0..local.len()).map(|t| local[t] + imported.get(t).copied().unwrap_or(0.0)).collect();
+
+        // total infectiousness of earlier cases on each day
+        let mut lambda = vec![0.0; local.len()];
+        for t in 0..local.len() {
+            for s in 1..self.serialInterval.len().min(t + 1) {
+                lambda[t] += total[t - s] * self.serialInterval[s];
+            }
+        }
+
+        let priorShape = (self.priorMean / self.priorSd).powi(2);
+        let priorScale = self.priorSd * self.priorSd / self.priorMean;
+        let mut res = Vec::new();
+        for t in self.window - 1..local.len() {
+            let cases: f64 = local[t + 1 - self.window..=t].iter().sum();
+            let pressure: f64 = lambda[t + 1 - self.window..=t].iter().sum();
+            if pressure <= 0.0 {
+                continue;
+            }
+            let shape = priorShape + cases;
+            let scale = 1.0 / (1.0 / priorScale + pressure);
+            res.push(RtEstimate {day: t, mean: shape * scale, sd: shape.sqrt() * scale, lower: gammaQuantile(shape, scale, -1.959964), upper: gammaQuantile(shape, scale, 1.959964)});
+        }
+        res
+    }
+
+    // Reads daily case counts, the last column of each line, skipping a header
+    pub fn casesFromCsv(file_path: &str) -> Result<Vec<f64>, Box<dyn Error>> {
+        let file = File::open(file_path)?;
+        let mut values = Vec::new();
+        let reader = io::BufReader::new(file);
+
+        for line in reader.lines() {
+            let line = line?;
+            if let Some(last) = line.trim().trim_end_matches(',').split(',').next_back() {
+                if let Ok(value) = last.trim().parse::<f64>() {
+                    values.push(value);
+                }
+            }
+        }
+
+        Ok(values)
+    }
+}
+
+// Wilson-Hilferty approximation of a gamma quantile, z is the standard normal quantile
+fn gammaQuantile(shape: f64, scale: f64, z: f64) -> f64 {
+    let c = 1.0 / (9.0 * shape);
+    let res = shape * scale * (1.0 - c + z * c.sqrt()).powi(3);
+    res.max(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn constantIncidenceHasRtOne() {
+        let estimator = CoriEstimator::new(CoriEstimator::gammaSerialInterval(5.0, 2.0, 20), 7);
+        let estimates = estimator.estimate(&vec![100.0; 60], &Vec::new());
+        // once the serial interval is covered every earlier case is counted in the pressure
+        for e in estimates.iter().filter(|e| e.day >= 30) {
+            assert!((e.mean - 1.0).abs() < 0.01, "day {}: {}", e.day, e.mean);
+            assert!(e.lower < 1.0 && e.upper > 1.0);
+        }
+    }
+
+    #[test]
+    fn firstEstimateEndsTheFirstFullWindow() {
+        let estimator = CoriEstimator::new(vec![0.0, 0.5, 0.5], 3);
+        let estimates = estimator.estimate(&[10.0; 10], &Vec::new());
+        assert_eq!(estimates[0].day, 2);
+        let single = CoriEstimator::new(vec![0.0, 1.0], 1).estimate(&[10.0; 10], &Vec::new());
+        assert_eq!(single[0].day, 1);
+    }
+}
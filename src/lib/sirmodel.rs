@@ -1,32 +1,101 @@
 
 
 
-use tokio::task::block_in_place;
 
 use crate::lib::intervention::InterventionType;
 
-use super::{matrix::Matrix, person::{Person, Personstate}, wgpuInit::WgpuInit, intervention::Intervention};
+use super::{matrix::Matrix, person::{Person, Personstate}, wgpuInit::WgpuInit, intervention::Intervention, distributions::{Distribution, samplePoisson}, infectiousness::InfectiousnessProfile, strain::Strain, forcing::Forcing, importation::{Importation, ImportPlacement}, spawn::SpawnDistribution, movement::{Movement, MovementModel}, rt::{CoriEstimator, RtEstimate}, transmissiontree::TransmissionTree, venue::{Venue, VenueCategory, nearestVenue}, person::Locations};
 use rand::{thread_rng,Rng};
+use std::sync::Arc;
 
 
 
+// Clones share the GPU context of the original, see setWgpuInit to give one its own
+#[derive(Clone)]
 pub struct SIRModel {
     population: Vec<Vec<Person>>,
     populationposvel: Vec<[Vec<f32>;4]>,
     populationinf: Vec<Vec<u32>>,
     populationInfDays: Vec<Vec<u32>>,
-    rnaught: Vec<f64>,
+    populationAlive: Vec<Vec<u32>>,
+    // day each person was last infected, and per infection day how many were infected and how many
+    // others they went on to infect
+    infectionDay: Vec<usize>,
+    cohortSize: Vec<usize>,
+    cohortSecondary: Vec<usize>,
+    transmissionTree: Option<TransmissionTree>,
     popsize: usize,
-    spreadMinMax: Matrix,
+    capacity: usize,
+    birthRate: f64,
+    deathRate: f64,
+    births: Vec<usize>,
+    deaths: Vec<usize>,
+    infectiousness: Distribution,
+    spreadChances: Vec<f64>,
+    // others infected by each infection so far, in the order the infections happened, and each
+    // person's latest infection in that list. Kept per infection so deaths and reused slots keep theirs
+    secondaryCases: Vec<u32>,
+    infectionIndex: Vec<usize>,
+    infectiousnessProfile: InfectiousnessProfile,
+    profileWeights: Vec<Vec<f64>>,
+    incubationPeriod: u32,
+    preSymptomaticScale: f64,
+    asymptomaticFraction: f64,
+    asymptomaticScale: f64,
+    asymptomatic: Vec<bool>,
+    symptomaticReportProb: f64,
+    asymptomaticReportProb: f64,
+    reportingDelay: Distribution,
+    reportedCases: Vec<usize>,
+    strains: Vec<Strain>,
+    crossImmunity: Vec<Vec<f64>>,
+    infectionStrain: Vec<usize>,
+    pastInfections: Vec<Vec<usize>>,
+    strainIncidence: Vec<Vec<usize>>,
+    forcing: Forcing,
+    importation: Importation,
+    importPlacement: ImportPlacement,
+    imported: Vec<usize>,
+    recoveries: Vec<usize>,
+    // chance an infection ends in death and the days from symptom onset to death, deathDay is
+    // set for the people who will die once they stop being infectious
+    infectionFatality: f64,
+    deathDelay: Distribution,
+    deathDay: Vec<Option<usize>>,
+    diseaseDeaths: Vec<usize>,
+    scaling: Scaling,
+    spawnDistribution: SpawnDistribution,
+    infectedCluster: Option<usize>,
+    movement: Movement,
+    homes: [Vec<f32>;2],
+    waypoints: [Vec<f32>;2],
+    // movement and contact checks per day, contacts between substeps are caught when swept
+    substeps: usize,
+    sweptContacts: bool,
+    venues: Vec<Venue>,
+    // venues each person goes to, one per category at most
+    venueAssignments: Vec<Vec<usize>>,
+    // chance each person is in the city on a given day, 1 for city residents
+    cityChance: Vec<f64>,
+    closedVenues: Vec<VenueCategory>,
+    venueInfections: Vec<usize>,
     spawnLoc: Matrix,
     infRad: f32,
     infectiousPeriod: usize,
     daysRun: usize,
-    wgpuinit: WgpuInit,
+    wgpuinit: Arc<WgpuInit>,
     simulated: bool,
     spreadRan: f64,
     interventions: Vec<Intervention>,
-    velocityMinMax: Matrix
+    // velocity multiplier during a Kkkkkzone and spread multiplier while masks are worn
+    lockdownFactor: f64,
+    maskFactor: f64,
+    // product of the mask factors in force, scales contact and venue transmission
+    maskScale: f64,
+    // product of the lockdown factors in force, for movement that redraws its velocity
+    speedScale: f64,
+    velocityMinMax: Matrix,
+    startInfNum: usize
 }
 
 //spreadran is actually the spread rate
@@ -34,11 +103,223 @@ pub struct SIRModel {
 impl SIRModel {
 
     pub fn setSpread(&mut self, spreadRate: f64) {
-        self.spreadMinMax = Matrix::from(vec![vec![spreadRate-(self.spreadRan/2.0),spreadRate+(self.spreadRan/2.0)]]);
         self.spreadRan = spreadRate;
         
     }
 
+    pub fn setForcing(&mut self, forcing: Forcing) {
+        self.forcing = forcing;
+    }
+
+    // Imported infections are added from day 1 onwards with the base strain
+    pub fn setImportation(&mut self, importation: Importation, importPlacement: ImportPlacement) {
+        self.importation = importation;
+        self.importPlacement = importPlacement;
+    }
+
+    // Per person per day birth and natural death rates. The population lives in a fixed number of
+    // slots, capacity, so it can grow past its starting size. The slots are resized straight away
+    pub fn setDemography(&mut self, birthRate: f64, deathRate: f64, capacity: usize) {
+        self.birthRate = birthRate;
+        self.deathRate = deathRate;
+        self.capacity = capacity.max(self.popsize);
+        if !self.populationAlive[0].is_empty() {
+            self.resizeSlots();
+        }
+    }
+
+    // Infection fatality ratio and the delay in days from symptom onset to death, for infected
+    // people who die. They stop being infectious at the end of their infectious period
+    pub fn setFatality(&mut self, infectionFatality: f64, deathDelay: Distribution) {
+        self.infectionFatality = infectionFatality;
+        self.deathDelay = deathDelay;
+    }
+
+    // Deaths per day caused by infection
+    pub fn getDiseaseDeaths(&mut self) -> Vec<usize> {
+        self.scaleCounts(self.diseaseDeaths.clone())
+    }
+
+    pub fn getBirths(&mut self) -> Vec<usize> {
+        self.scaleCounts(self.births.clone())
+    }
+
+    // Natural deaths per day, see getDiseaseDeaths for deaths from infection
+    pub fn getDeaths(&mut self) -> Vec<usize> {
+        self.scaleCounts(self.deaths.clone())
+    }
+
+    // Number of people alive on each day
+    pub fn getAlive(&mut self) -> Vec<usize> {
+        let alive = (0..self.daysRun).map(|day| self.aliveOn(day)).collect();
+        self.scaleCounts(alive)
+    }
+
+    // Imported infections per day, these are also counted in the incidence of their strain
+    pub fn getImported(&mut self) -> Vec<usize> {
+        self.scaleCounts(self.imported.clone())
+    }
+
+    // New infections per day that happened inside venues
+    pub fn getVenueInfections(&mut self) -> Vec<usize> {
+        self.scaleCounts(self.venueInfections.clone())
+    }
+
+    // Density resizes the arena straight away and places everyone in it again, AgentWeight scales
+    // every count this model outputs
+    pub fn setScaling(&mut self, scaling: Scaling) {
+        if let Scaling::Density(density) = scaling {
+            let side = ((self.popsize as f64) / density).sqrt();
+            self.spawnLoc = Matrix::from(vec![vec![side, side]]);
+            self.respawn();
+        }
+        self.scaling = scaling;
+    }
+
+    // Initial placement, applied from the next clearOut. With Clusters the starting infections can
+    // all be put in one cluster. Returns false and keeps the current placement when there are no
+    // clusters or points, or infectedCluster is not one of the clusters
+    pub fn setSpawn(&mut self, spawnDistribution: SpawnDistribution, infectedCluster: Option<usize>) -> bool {
+        let valid = match &spawnDistribution {
+            SpawnDistribution::Clusters(clusters) => !clusters.is_empty() && infectedCluster.is_none_or(|c| c < clusters.len()),
+            SpawnDistribution::Points(points) => !points.is_empty(),
+            _ => true
+        };
+        if !valid {
+            return false;
+        }
+        self.spawnDistribution = spawnDistribution;
+        self.infectedCluster = infectedCluster;
+        true
+    }
+
+    // Number of real people each agent stands for
+    pub fn getAgentWeight(&mut self) -> f64 {
+        match self.scaling {
+            Scaling::AgentWeight(weight) => weight,
+            _ => 1.0
+        }
+    }
+
+    // Agents standing for count real people out of realPopSize, by the agent weight when one is set
+    // and otherwise by the share of the population. At least one agent for a nonzero count
+    pub fn agentsFor(&mut self, count: f64, realPopSize: usize) -> f64 {
+        let agents = match self.scaling {
+            Scaling::AgentWeight(weight) => count / weight,
+            _ => count * (self.popsize as f64) / (realPopSize as f64)
+        };
+        if count > 0.0 { agents.round().max(1.0) } else { 0.0 }
+    }
+
+    fn scaleCounts(&mut self, counts: Vec<usize>) -> Vec<usize> {
+        let weight = self.getAgentWeight();
+        counts.into_iter().map(|c| ((c as f64) * weight).round() as usize).collect()
+    }
+
+    // Sets a parameter the trainer can calibrate by name, returns false for unknown names
+    // Population, infection period and velocity parameters only take effect at the next clearOut
+    pub fn setParam(&mut self, name: &str, value: f64) -> bool {
+        match name {
+            "spread" => self.setSpread(value),
+            "infRad" => self.infRad = value as f32,
+            "infectiousPeriod" => self.infectiousPeriod = value.round().max(1.0) as usize,
+            "startInfNum" => self.startInfNum = value.round().max(0.0) as usize,
+            "minVelocity" => self.velocityMinMax.data[0][0] = value,
+            "maxVelocity" => self.velocityMinMax.data[0][1] = value,
+            // kept above zero as the intervention ending divides by them
+            "lockdownFactor" => self.lockdownFactor = value.max(1e-6),
+            "maskFactor" => self.maskFactor = value.max(1e-6),
+            "infectionFatality" => self.infectionFatality = value.clamp(0.0, 1.0),
+            // only a sinusoidal forcing has these, set it with setForcing first
+            "seasonAmplitude" | "seasonPhase" => match &mut self.forcing {
+                Forcing::Sinusoidal { amplitude, phase, .. } => {
+                    if name == "seasonAmplitude" {
+                        *amplitude = value;
+                    } else {
+                        *phase = value;
+                    }
+                },
+                _ => return false
+            },
+            _ => return self.movement.setParam(name, value)
+        }
+        true
+    }
+
+    pub fn setMovement(&mut self, movement: Movement) {
+        self.movement = movement;
+    }
+
+    // Venues people visit on top of meeting in the open, applied from the next clearOut
+    pub fn setVenues(&mut self, venues: Vec<Venue>) {
+        self.venues = venues;
+    }
+
+    // Splits each day into substeps moves, each checked for contacts. With swept the whole path
+    // of every substep is checked so fast movers can't jump past each other
+    pub fn setSubsteps(&mut self, substeps: usize, swept: bool) {
+        self.substeps = substeps.max(1);
+        self.sweptContacts = swept;
+    }
+
+    // Distribution of each person's relative infectiousness, applied from the next clearOut
+    pub fn setInfectiousness(&mut self, infectiousness: Distribution) {
+        self.infectiousness = infectiousness;
+    }
+
+    pub fn setInfectiousnessProfile(&mut self, profile: InfectiousnessProfile) {
+        self.infectiousnessProfile = profile;
+        self.profileWeights = self.strains.clone().into_iter().map(|mut s| self.infectiousnessProfile.weights(s.getInfectiousPeriod())).collect();
+    }
+
+    // Adds a strain seeded on its seed day, fully cross-protected by every other strain until
+    // setCrossImmunity says otherwise. Returns the strain index, the base strain is 0
+    pub fn addStrain(&mut self, mut strain: Strain) -> usize {
+        for row in self.crossImmunity.iter_mut() {
+            row.push(1.0);
+        }
+        self.strains.push(strain);
+        self.crossImmunity.push(vec![1.0;self.strains.len()]);
+        self.profileWeights.push(self.infectiousnessProfile.weights(strain.getInfectiousPeriod()));
+        self.strainIncidence.push(vec![0;self.daysRun]);
+        self.strains.len() - 1
+    }
+
+    // Protection against strain `to` given by a past infection with strain `from`
+    pub fn setCrossImmunity(&mut self, from: usize, to: usize, protection: f64) {
+        self.crossImmunity[from][to] = protection;
+    }
+
+    // New infections per day for each strain
+    pub fn strainIncidence(&mut self) -> Vec<Vec<usize>> {
+        self.strainIncidence.clone().into_iter().map(|counts| self.scaleCounts(counts)).collect()
+    }
+
+    // Days up to incubationPeriod are pre-symptomatic, asymptomatic people are drawn at clearOut
+    pub fn setSymptoms(&mut self, incubationPeriod: u32, preSymptomaticScale: f64, asymptomaticFraction: f64, asymptomaticScale: f64) {
+        self.incubationPeriod = incubationPeriod;
+        self.preSymptomaticScale = preSymptomaticScale;
+        self.asymptomaticFraction = asymptomaticFraction;
+        self.asymptomaticScale = asymptomaticScale;
+    }
+
+    // Probability an infection is reported and the delay in days from infection to report
+    pub fn setReporting(&mut self, symptomaticReportProb: f64, asymptomaticReportProb: f64, reportingDelay: Distribution) {
+        self.symptomaticReportProb = symptomaticReportProb;
+        self.asymptomaticReportProb = asymptomaticReportProb;
+        self.reportingDelay = reportingDelay;
+    }
+
+    // clearOut with the current (possibly calibrated) number of initial infections
+    pub fn reset(&mut self) {
+        self.clearOut(self.startInfNum);
+    }
+
+    // Runs the simulation on another device and queue, so copies of this model can run side by side
+    pub fn setWgpuInit(&mut self, wgpuinit: WgpuInit) {
+        self.wgpuinit = Arc::new(wgpuinit);
+    }
+
     pub fn setDays(&mut self, days: usize) {
         self.daysRun = days;
         println!("DAHS");
@@ -47,82 +328,231 @@ impl SIRModel {
     
 
     pub fn clearOut(&mut self, startInfNum: usize) {
-        
+        let startInfNum = startInfNum.min(self.popsize);
+        self.startInfNum = startInfNum;
         self.population = vec![Vec::new();self.daysRun];
         self.populationposvel = vec![[Vec::new(),Vec::new(),Vec::new(),Vec::new()];self.daysRun];
         self.populationinf = vec![Vec::new();self.daysRun];
         self.populationInfDays = vec![Vec::new();self.daysRun];
-        self.rnaught = vec![0.0;self.daysRun];
+        self.populationAlive = vec![Vec::new();self.daysRun];
+        self.cohortSize = vec![0;self.daysRun];
+        self.cohortSecondary = vec![0;self.daysRun];
         println!("{:?}", self.popsize);
-        for i in 0..(self.popsize-startInfNum) {
-            self.population[0].push(Person::random(Personstate::Sus,self.spreadMinMax.clone(), self.spawnLoc.clone(), self.velocityMinMax.clone()));
+        self.capacity = self.capacity.max(self.popsize);
+        self.infectionDay = vec![0;self.capacity];
+        if self.transmissionTree.is_some() {
+            self.transmissionTree = Some(TransmissionTree::new(self.capacity));
+        }
+        self.births = vec![0;self.daysRun];
+        self.deaths = vec![0;self.daysRun];
+        self.spreadChances = Vec::new();
+        self.secondaryCases = Vec::new();
+        self.infectionIndex = vec![0;self.capacity];
+        self.strains[0].setInfectiousPeriod(self.infectiousPeriod);
+        self.profileWeights = self.strains.clone().into_iter().map(|mut s| self.infectiousnessProfile.weights(s.getInfectiousPeriod())).collect();
+        self.asymptomatic = Vec::new();
+        self.reportedCases = vec![0;self.daysRun];
+        self.infectionStrain = vec![0;self.capacity];
+        self.pastInfections = vec![Vec::new();self.capacity];
+        self.strainIncidence = vec![vec![0;self.daysRun];self.strains.len()];
+        self.imported = vec![0;self.daysRun];
+        self.recoveries = vec![0;self.daysRun];
+        self.deathDay = vec![None;self.capacity];
+        self.diseaseDeaths = vec![0;self.daysRun];
+        self.homes = [Vec::new(),Vec::new()];
+        self.waypoints = [Vec::new(),Vec::new()];
+        self.venueAssignments = Vec::new();
+        self.cityChance = Vec::new();
+        self.maskScale = 1.0;
+        self.speedScale = 1.0;
+        self.closedVenues = Vec::new();
+        self.venueInfections = vec![0;self.daysRun];
+        for _ in 0..(self.popsize-startInfNum) {
+            self.population[0].push(Person::random(Personstate::Sus,&self.infectiousness, self.spawnLoc.clone(), self.velocityMinMax.clone()));
             self.populationInfDays[0].push(0);
         }
-        for i in 0..startInfNum {
-            self.population[0].push(Person::random(Personstate::Inf,self.spreadMinMax.clone(), self.spawnLoc.clone(), self.velocityMinMax.clone()));
+        for _ in 0..startInfNum {
+            self.population[0].push(Person::random(Personstate::Inf,&self.infectiousness, self.spawnLoc.clone(), self.velocityMinMax.clone()));
             self.populationInfDays[0].push(1);
         }
-        for i in 0..self.popsize {
-            self.populationposvel[0][0].push(self.population[0][i].getPosVel()[0]);
-            self.populationposvel[0][1].push(self.population[0][i].getPosVel()[1]);
-            self.populationposvel[0][2].push(self.population[0][i].getPosVel()[2]);
-            self.populationposvel[0][3].push(self.population[0][i].getPosVel()[3]);
-            self.populationinf[0].push(boolToU32(self.population[0][i].infectCheck()))
+        // empty slots for births
+        for _ in self.popsize..self.capacity {
+            self.population[0].push(Person::random(Personstate::Sus,&self.infectiousness, self.spawnLoc.clone(), self.velocityMinMax.clone()));
+            self.populationInfDays[0].push(0);
+        }
+        for i in 0..self.capacity {
+            self.placeSlot(i);
+            if self.populationinf[0][i] == 1 {
+                self.infect(i, None, 0, 0);
+            }
         }
+        self.seedStrains(0);
         println!("Days Run: {:?}",self.daysRun);
         println!("Population Size: {:?}",self.popsize);
         println!("populationInfDays len: {:?}", self.populationInfDays.len());
     }
 
-    pub fn emptyTZero(popsize: usize, days: usize, wgpuinit: WgpuInit)-> SIRModel {
+    // Places the person in slot i of day 0 and sets up the rest of the slot's state
+    fn placeSlot(&mut self, i: usize) {
+        let mut rng = rand::thread_rng();
+        let infected = self.population[0][i].infectCheck();
+        let pos = self.spawnPosition(i, infected);
+        self.population[0][i].setPos(pos.0, pos.1);
+        self.homes[0].push(pos.0);
+        self.homes[1].push(pos.1);
+        self.waypoints[0].push(rng.gen::<f32>() * self.spawnLoc.get(0, 0) as f32);
+        self.waypoints[1].push(rng.gen::<f32>() * self.spawnLoc.get(0, 1) as f32);
+        self.populationAlive[0].push(boolToU32(i < self.popsize));
+        let posvel = self.population[0][i].getPosVel();
+        for (column, value) in self.populationposvel[0].iter_mut().zip(posvel) {
+            column.push(value);
+        }
+        self.populationinf[0].push(boolToU32(infected));
+        self.spreadChances.push(self.population[0][i].getSpreadChance());
+        self.asymptomatic.push(rng.gen::<f64>() < self.asymptomaticFraction);
+        let assigned = self.assignVenues(pos);
+        self.venueAssignments.push(assigned);
+        let cityChance = Self::cityChanceOf(&mut self.population[0][i]);
+        self.cityChance.push(cityChance);
+    }
+
+    // Places every day 0 slot again after the arena changed, with new homes, waypoints and venues
+    fn respawn(&mut self) {
+        let mut rng = rand::thread_rng();
+        for i in 0..self.populationAlive[0].len() {
+            let infected = self.population[0][i].infectCheck();
+            let pos = self.spawnPosition(i, infected);
+            self.population[0][i].setPos(pos.0, pos.1);
+            self.populationposvel[0][0][i] = pos.0;
+            self.populationposvel[0][1][i] = pos.1;
+            self.homes[0][i] = pos.0;
+            self.homes[1][i] = pos.1;
+            self.waypoints[0][i] = rng.gen::<f32>() * self.spawnLoc.get(0, 0) as f32;
+            self.waypoints[1][i] = rng.gen::<f32>() * self.spawnLoc.get(0, 1) as f32;
+            let assigned = self.assignVenues(pos);
+            self.venueAssignments[i] = assigned;
+        }
+    }
+
+    // Grows or shrinks the day 0 state to capacity slots, new slots are empty ones for births.
+    // Later days are copied from day 0 as the model runs
+    fn resizeSlots(&mut self) {
+        let capacity = self.capacity;
+        for column in self.populationposvel[0].iter_mut() {
+            column.truncate(capacity);
+        }
+        self.population[0].truncate(capacity);
+        self.populationInfDays[0].truncate(capacity);
+        self.populationinf[0].truncate(capacity);
+        self.populationAlive[0].truncate(capacity);
+        for side in 0..2 {
+            self.homes[side].truncate(capacity);
+            self.waypoints[side].truncate(capacity);
+        }
+        self.spreadChances.truncate(capacity);
+        self.asymptomatic.truncate(capacity);
+        self.venueAssignments.truncate(capacity);
+        self.cityChance.truncate(capacity);
+        for i in self.population[0].len()..capacity {
+            self.population[0].push(Person::random(Personstate::Sus,&self.infectiousness, self.spawnLoc.clone(), self.velocityMinMax.clone()));
+            self.populationInfDays[0].push(0);
+            self.placeSlot(i);
+        }
+        self.infectionDay.resize(capacity, 0);
+        self.infectionIndex.resize(capacity, 0);
+        self.infectionStrain.resize(capacity, 0);
+        self.pastInfections.resize(capacity, Vec::new());
+        self.deathDay.resize(capacity, None);
+        if let Some(tree) = self.transmissionTree.as_mut() {
+            tree.setCapacity(capacity);
+        }
+    }
+
+    pub fn emptyTZero(_popsize: usize, days: usize, wgpuinit: WgpuInit)-> SIRModel {
         SIRModel {
             population: vec![Vec::new();days],
             populationposvel: vec![[Vec::new(),Vec::new(),Vec::new(),Vec::new()];days],
             populationinf: vec![Vec::new();days],
             populationInfDays: vec![Vec::new(); days],
-            rnaught: vec![0.0;days],
+            populationAlive: vec![Vec::new(); days],
+            infectionDay: Vec::new(),
+            cohortSize: vec![0;days],
+            cohortSecondary: vec![0;days],
+            transmissionTree: None,
             popsize: 1,
-            spreadMinMax: Matrix::zeros(1, 2),
+            capacity: 1,
+            birthRate: 0.0,
+            deathRate: 0.0,
+            births: vec![0;days],
+            deaths: vec![0;days],
+            infectiousness: Distribution::Constant(1.0),
+            spreadChances: Vec::new(),
+            secondaryCases: Vec::new(),
+            infectionIndex: Vec::new(),
+            infectiousnessProfile: InfectiousnessProfile::Flat,
+            profileWeights: Vec::new(),
+            incubationPeriod: 0,
+            preSymptomaticScale: 1.0,
+            asymptomaticFraction: 0.0,
+            asymptomaticScale: 1.0,
+            asymptomatic: Vec::new(),
+            symptomaticReportProb: 1.0,
+            asymptomaticReportProb: 0.0,
+            reportingDelay: Distribution::Constant(0.0),
+            reportedCases: vec![0;days],
+            strains: vec![Strain::new(1.0, 7, 0.0, 0, 0)],
+            crossImmunity: vec![vec![1.0]],
+            infectionStrain: Vec::new(),
+            pastInfections: Vec::new(),
+            strainIncidence: Vec::new(),
+            forcing: Forcing::None,
+            importation: Importation::None,
+            importPlacement: ImportPlacement::Random,
+            imported: vec![0;days],
+            recoveries: vec![0;days],
+            infectionFatality: 0.0,
+            deathDelay: Distribution::Constant(14.0),
+            deathDay: Vec::new(),
+            diseaseDeaths: vec![0;days],
+            scaling: Scaling::None,
+            spawnDistribution: SpawnDistribution::Uniform,
+            infectedCluster: None,
+            movement: Movement::new(MovementModel::Straight),
+            homes: [Vec::new(),Vec::new()],
+            waypoints: [Vec::new(),Vec::new()],
+            substeps: 1,
+            sweptContacts: false,
+            venues: Vec::new(),
+            venueAssignments: Vec::new(),
+            cityChance: Vec::new(),
+            closedVenues: Vec::new(),
+            venueInfections: vec![0;days],
             spawnLoc: Matrix::zeros(1, 2),
             infRad:0.0,
             infectiousPeriod:7,
             daysRun: days,
-            wgpuinit: wgpuinit,
+            wgpuinit: Arc::new(wgpuinit),
             simulated: false,
             spreadRan: 0.0,
             interventions: Vec::new(),
-            velocityMinMax: Matrix::zeros(1,2)
+            lockdownFactor: 0.05,
+            maskFactor: 0.4,
+            maskScale: 1.0,
+            speedScale: 1.0,
+            velocityMinMax: Matrix::zeros(1,2),
+            startInfNum: 0
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn new(popsize: usize, infRad:f32, infectiousPeriod:usize, startInfNum:usize, spreadRate: f64, spreadRan: f64, spawn_x: f64, spawn_y: f64, minVelocity: f64, maxVelocity: f64, daysRun: usize, wgpuinit: WgpuInit, interventions: Vec<Intervention>) -> SIRModel {
-        let mut spawnLoc = Matrix::from(vec![vec![spawn_x*0.5,spawn_y*0.5]]);
-        let mut spreadMinMax = Matrix::from(vec![vec![spreadRate-(spreadRan/2.0),spreadRate+(spreadRan/2.0)]]);
-        let mut velocityMinMax = Matrix::from(vec![vec![minVelocity,maxVelocity]]);
+        let spawnLoc = Matrix::from(vec![vec![spawn_x*0.5,spawn_y*0.5]]);
+        let velocityMinMax = Matrix::from(vec![vec![minVelocity,maxVelocity]]);
         let mut res = SIRModel::emptyTZero(popsize, daysRun, wgpuinit);
-        for i in 0..(popsize-startInfNum) {
-            res.population[0].push(Person::random(Personstate::Sus,spreadMinMax.clone(), spawnLoc.clone(), velocityMinMax.clone()));
-        }
-        for i in 0..startInfNum {
-            res.population[0].push(Person::random(Personstate::Inf,spreadMinMax.clone(), spawnLoc.clone(), velocityMinMax.clone()));
-    
-        }
-
-        println!("{:?}", res.population[0].len());
-
-        for i in 0..popsize {
-            res.populationposvel[0][0].push(res.population[0][i].getPosVel()[0]);
-            res.populationposvel[0][1].push(res.population[0][i].getPosVel()[1]);
-            res.populationposvel[0][2].push(res.population[0][i].getPosVel()[2]);
-            res.populationposvel[0][3].push(res.population[0][i].getPosVel()[3]);
-            res.populationinf[0].push(boolToU32(res.population[0][i].infectCheck()));
-            res.populationInfDays[0].push(0);
-        }
-
-        res.rnaught = vec![0.0;daysRun];
 
         res.popsize = popsize;
-        res.spreadMinMax = spreadMinMax;
+        // spreadRan is the width of the default uniform infectiousness around 1
+        res.infectiousness = Distribution::Uniform(1.0-(spreadRan/2.0),1.0+(spreadRan/2.0));
         res.spreadRan = spreadRate;
         //println!("x: {:?}, y: {:?}",spawnLoc.clone().data[0][0],spawnLoc.clone().data[0][1]);
         res.spawnLoc = spawnLoc;
@@ -130,11 +560,69 @@ impl SIRModel {
         res.infectiousPeriod = infectiousPeriod;
         res.interventions = interventions;
         res.velocityMinMax = velocityMinMax;
+        res.clearOut(startInfNum);
         res
     }
 
+    // Cohort reproduction number: mean number of people infected by those infected on each day.
+    // The last infectious period is censored, those people had no time to infect everyone yet
     pub fn getrnaught(&mut self) -> Vec<f64> {
-        return self.rnaught.clone();
+        let mut res = vec![0.0;self.daysRun];
+        for (day, r) in res.iter_mut().enumerate() {
+            if self.cohortSize[day] > 0 {
+                *r = (self.cohortSecondary[day] as f64) / (self.cohortSize[day] as f64);
+            }
+        }
+        res
+    }
+
+    // Keeps a who-infected-whom tree, off by default as it stores every infection. It starts from the
+    // people infected on day 0, so turn it on before running
+    pub fn recordTransmissions(&mut self, record: bool) {
+        self.transmissionTree = None;
+        if !record {
+            return;
+        }
+        let mut tree = TransmissionTree::new(self.capacity);
+        for p in 0..self.populationinf[0].len() {
+            if self.populationinf[0][p] == 1 {
+                let onset = if self.asymptomatic[p] { None } else { Some(self.incubationPeriod as usize) };
+                tree.record(p, None, 0, self.infectionStrain[p], onset);
+            }
+        }
+        self.transmissionTree = Some(tree);
+    }
+
+    pub fn getTransmissionTree(&mut self) -> Option<TransmissionTree> {
+        self.transmissionTree.clone()
+    }
+
+    // Instantaneous R_t from the simulated incidence with the Cori estimator over a trailing window,
+    // using the base strain's generation interval
+    pub fn rtCori(&mut self, window: usize) -> Vec<RtEstimate> {
+        let mut serialInterval = vec![0.0];
+        serialInterval.extend(self.profileWeights[0].iter());
+        let estimator = CoriEstimator::new(serialInterval, window);
+        let imported: Vec<f64> = self.imported[0..self.daysRun].iter().map(|c| *c as f64).collect();
+        let local: Vec<f64> = self.incidence().iter().zip(imported.iter()).map(|(c, i)| (*c as f64) - i).collect();
+        estimator.estimate(&local, &imported)
+    }
+
+    // Histogram of secondary cases: entry n is the number of infections that went on to infect n
+    // others, counting everyone ever infected including the dead. Empty if no days were run
+    pub fn offspringDistribution(&mut self) -> Vec<usize> {
+        if self.daysRun == 0 {
+            return Vec::new();
+        }
+        let mut res = vec![0];
+        for cases in &self.secondaryCases {
+            let n = *cases as usize;
+            if n >= res.len() {
+                res.resize(n + 1, 0);
+            }
+            res[n] += 1;
+        }
+        res
     }
 
     pub async fn runSim(&mut self) {
@@ -147,37 +635,104 @@ impl SIRModel {
         self.simulated = true;
     }
 
+    // Living people who are infected or have recovered on each day
     pub fn numInfected(&mut self) -> Vec<usize> {
-        let mut c = vec![0];
-        println!("{:?}",self.daysRun);
-        for i in 0..self.daysRun {
-            c.push(0);
+        let mut c = vec![0;self.daysRun];
+        for (i, count) in c.iter_mut().enumerate() {
             for j in 0..self.populationinf[i].len() {
-                if self.populationinf[i][j] > 0 {
-                    c[i] = c[i] + 1;
+                if self.populationinf[i][j] > 0 && self.populationAlive[i][j] == 1 {
+                    *count += 1;
                 }
             }
-            //c[i] = c[i] / self.popsize;
         }
-        c
+        self.scaleCounts(c)
+    }
+
+    // New infections on each day, including seeded and imported ones
+    pub fn newInfections(&mut self) -> Vec<usize> {
+        let res = self.incidence();
+        self.scaleCounts(res)
+    }
+
+    pub fn newRecoveries(&mut self) -> Vec<usize> {
+        self.scaleCounts(self.recoveries[0..self.daysRun].to_vec())
+    }
+
+    pub fn cumulativeInfections(&mut self) -> Vec<usize> {
+        let res = cumulative(&self.incidence());
+        self.scaleCounts(res)
+    }
+
+    // Infections over the whole run per person who was ever alive, reinfections count again
+    pub fn attackRate(&mut self) -> f64 {
+        let infections: usize = self.incidence().iter().sum();
+        let everAlive = self.popsize + self.births[0..self.daysRun].iter().sum::<usize>();
+        (infections as f64) / (everAlive as f64)
+    }
+
+    // Day with the most people infected and how many there were
+    pub fn peak(&mut self) -> (usize, usize) {
+        let prevalence = self.prevalence();
+        let mut day = 0;
+        for i in 0..prevalence.len() {
+            if prevalence[i] > prevalence[day] {
+                day = i;
+            }
+        }
+        let size = ((prevalence[day] as f64) * self.getAgentWeight()).round() as usize;
+        (day, size)
+    }
+
+    // New reported cases on each day
+    pub fn reportedCases(&mut self) -> Vec<usize> {
+        self.scaleCounts(self.reportedCases[0..self.daysRun].to_vec())
+    }
+
+    pub fn cumulativeReported(&mut self) -> Vec<usize> {
+        let res = cumulative(&self.reportedCases[0..self.daysRun].to_vec());
+        self.scaleCounts(res)
+    }
+
+    // Daily series of the chosen observable as a proportion of the population
+    pub fn observe(&mut self, observable: Observable) -> Vec<f64> {
+        let counts = match observable {
+            Observable::Prevalence => return self.propInfected(),
+            Observable::Incidence => self.incidence(),
+            Observable::Cumulative => cumulative(&self.incidence()),
+            Observable::Reported => self.reportedCases[0..self.daysRun].to_vec(),
+            Observable::CumulativeReported => cumulative(&self.reportedCases[0..self.daysRun].to_vec()),
+            Observable::Deaths => self.diseaseDeaths[0..self.daysRun].to_vec()
+        };
+        counts.into_iter().enumerate().map(|(day, c)| self.proportionAlive(c, day)).collect()
     }
 
     pub fn propInfected(&mut self) -> Vec<f64> {
-        let mut c = vec![0];
-        let mut res = Vec::new();
-        println!("{:?}",self.daysRun);
-        for i in 0..self.daysRun {
-            c.push(0);
+        let c = self.prevalence();
+        c.iter().enumerate().map(|(day, count)| self.proportionAlive(*count, day)).collect()
+    }
+
+    // Unscaled number of living infected people on each day
+    fn prevalence(&self) -> Vec<usize> {
+        let mut c = vec![0;self.daysRun];
+        for (i, count) in c.iter_mut().enumerate() {
             for j in 0..self.populationinf[i].len() {
-                if self.populationinf[i][j] == 1 {
-                    c[i] = c[i] + 1;
+                if self.populationinf[i][j] == 1 && self.populationAlive[i][j] == 1 {
+                    *count += 1;
                 }
             }
-            //c[i] = c[i];
-            res.push((c[i] as f64)/(self.popsize as f64));
         }
-        println!("{:?}", c);
-        res
+        c
+    }
+
+    // Unscaled new infections on each day over all strains
+    fn incidence(&self) -> Vec<usize> {
+        let mut c = vec![0;self.daysRun];
+        for strain in &self.strainIncidence {
+            for i in 0..self.daysRun {
+                c[i] += strain[i];
+            }
+        }
+        c
     }
 
     
@@ -196,7 +751,7 @@ impl SIRModel {
     pub fn exportMatrixAllDays(&mut self) -> Vec<Vec<usize>> {
         let mut res = vec![vec![]];
         for day in 0..self.daysRun {
-            let mut tempDay =vec![self.getNumInfected(day),self.getNumSusceptible(day),self.getNumRemoved(day)];
+            let tempDay =vec![self.getNumInfected(day),self.getNumSusceptible(day),self.getNumRemoved(day)];
             res.push(tempDay);
         }
         res
@@ -208,33 +763,33 @@ impl SIRModel {
 
     pub fn getNumSusceptible(&mut self, day: usize) -> usize {
         let mut res = 0;
-        for mut per in self.population[day].clone() {
-            if per.getState() == Personstate::Sus {
+        for j in 0..self.populationinf[day].len() {
+            if self.populationinf[day][j] == 0 && self.populationAlive[day][j] == 1 {
                 res +=1;
             }
         }
-        res
+        ((res as f64) * self.getAgentWeight()).round() as usize
     }
 
     pub fn getNumRemoved(&mut self, day: usize) -> usize {
         let mut res = 0;
-        for mut per in self.population[day].clone() {
-            if per.getState() == Personstate::Rem {
+        for j in 0..self.populationinf[day].len() {
+            if self.populationinf[day][j] == 2 && self.populationAlive[day][j] == 1 {
                 res +=1;
             }
         }
-        res
+        ((res as f64) * self.getAgentWeight()).round() as usize
     }
 
 
-    pub fn getNumInfected(&mut self, day: usize ) -> usize {
+    pub fn getNumInfected(&mut self, day: usize) -> usize {
         let mut res = 0;
-        for mut per in self.population[day].clone() {
-            if per.getState() == Personstate::Inf {
+        for j in 0..self.populationinf[day].len() {
+            if self.populationinf[day][j] == 1 && self.populationAlive[day][j] == 1 {
                 res +=1;
             }
         }
-        res
+        ((res as f64) * self.getAgentWeight()).round() as usize
     }
 
 
@@ -247,6 +802,7 @@ impl SIRModel {
 
         if time > 0 && self.infRad > 0.0{
             self.populationInfDays[time] = self.populationInfDays[time-1].clone();
+            self.populationAlive[time] = self.populationAlive[time-1].clone();
             for mut int in self.interventions.clone() {
                 if time == int.getStart() {
                     match int.getType() {
@@ -254,16 +810,20 @@ impl SIRModel {
                             let mut resula = Vec::new();
                             let mut resulb = Vec::new();
                             for p in self.populationposvel[time-1][2].clone() {
-                                resula.push(p*0.05);
+                                resula.push(p*(self.lockdownFactor as f32));
                             }
                             for p in self.populationposvel[time-1][3].clone() {
-                                resulb.push(p*0.05);
+                                resulb.push(p*(self.lockdownFactor as f32));
                             }
                             self.populationposvel[time-1][2] = resula;
                             self.populationposvel[time-1][3] = resulb;
+                            self.speedScale *= self.lockdownFactor;
                         },
                         InterventionType::Mask => {
-                            self.spreadRan = self.spreadRan * 0.4;
+                            self.maskScale *= self.maskFactor;
+                        },
+                        InterventionType::CloseVenues(category) => {
+                            self.closedVenues.push(category);
                         }
                     }
                     
@@ -273,16 +833,22 @@ impl SIRModel {
                             let mut resula = Vec::new();
                             let mut resulb = Vec::new();
                             for p in self.populationposvel[time-1][2].clone() {
-                                resula.push(p*20.0);
+                                resula.push(p/(self.lockdownFactor as f32));
                             }
                             for p in self.populationposvel[time-1][3].clone() {
-                                resulb.push(p*20.0);
+                                resulb.push(p/(self.lockdownFactor as f32));
                             }
                             self.populationposvel[time-1][2] = resula;
                             self.populationposvel[time-1][3] = resulb;
+                            self.speedScale /= self.lockdownFactor;
                         },
                         InterventionType::Mask => {
-                            self.spreadRan = self.spreadRan / 0.4;
+                            self.maskScale /= self.maskFactor;
+                        },
+                        InterventionType::CloseVenues(category) => {
+                            if let Some(i) = self.closedVenues.iter().position(|c| *c == category) {
+                                self.closedVenues.remove(i);
+                            }
                         }
                     }
                 }
@@ -290,44 +856,370 @@ impl SIRModel {
 
             //println!("popinf legnth: {:?}", self.populationposvel[time-1][0]);
 
-            self.populationposvel[time] =self.wgpuinit.moveCol(self.populationposvel[time-1][0].clone(), self.populationposvel[time-1][1].clone(), self.populationposvel[time-1][2].clone(), self.populationposvel[time-1][3].clone(), [self.spawnLoc.get(0, 0) as f32,self.spawnLoc.get(0, 1)as f32] ).await;
-            self.populationinf[time] = self.wgpuinit.checkInf(self.populationposvel[time][0].clone(), self.populationposvel[time][1].clone(), self.populationinf[time-1].clone(), self.infRad*self.infRad).await;
-            
+            // move in substeps, keeping the first contact each person had during the day
+            let dt = 1.0 / self.substeps as f32;
+            let mut posvel = self.populationposvel[time-1].clone();
+            let mut contacts = vec![0u32; posvel[0].len()];
+            for _ in 0..self.substeps {
+                let moveParams = self.movement.uniform(rng.gen::<u32>(), dt, self.speedScale as f32);
+                let (newposvel, waypoints) = self.wgpuinit.moveCol(posvel[0].clone(), posvel[1].clone(), posvel[2].clone(), posvel[3].clone(), self.populationAlive[time].clone(), self.homes.clone(), self.waypoints.clone(), [self.spawnLoc.get(0, 0) as f32,self.spawnLoc.get(0, 1)as f32], moveParams).await;
+                self.waypoints = waypoints;
+                let stepContacts = self.wgpuinit.checkInf(newposvel[0].clone(), newposvel[1].clone(), posvel[0].clone(), posvel[1].clone(), self.populationinf[time-1].clone(), self.populationAlive[time].clone(), self.infRad*self.infRad, self.sweptContacts).await;
+                for p in 0..contacts.len() {
+                    if contacts[p] == 0 {
+                        contacts[p] = stepContacts[p];
+                    }
+                }
+                posvel = newposvel;
+            }
+            self.populationposvel[time] = posvel;
+            self.populationinf[time] = self.populationinf[time-1].clone();
+            let forcing = self.forcing.multiplier(time);
 
-            let mut count = 0.0;
-            for p in 0..self.populationinf[time].len() {
-                if self.populationinf[time-1][p] == 0 {
-                    if self.populationinf[time][p] == 1 {
-                        //println!("asdfbaf");
-                        //println!("{:?}", self.spreadRan);
+            for (p, contact) in contacts.into_iter().enumerate() {
+                if self.populationinf[time-1][p] != 1 && contact > 0 {
+                    let infector = (contact - 1) as usize;
+                    let strain = self.infectionStrain[infector];
+                    let susceptibility = self.susceptibility(p, strain, time-1);
+                    if susceptibility > 0.0 {
                         let random = rng.gen::<f64>();
-                        count += 1.0;
                         //println!("CHECKS: {:?}", random);
-                        if random > self.spreadRan {
-                            //println!("checkasdf");
-                            self.populationinf[time][p] = 0;
+                        if random < self.spreadRan * self.maskScale * forcing * self.strains[strain].getTransmissibility() * self.spreadChances[infector] * self.relativeInfectiousness(infector, time-1) * susceptibility {
+                            self.transmit(infector, p, time);
                         } 
                     }
-
-                    
                 }
             }
-            count = count / (self.numInfected()[time] as f64) * (self.spreadRan as f64);
-            self.rnaught[time] = count; 
-
+            self.venueTransmission(time);
 
-            for p in 0..self.populationinf.len() {
+            for p in 0..self.populationinf[time].len() {
                 if self.populationinf[time][p] == 1 {
-                    self.populationInfDays[time][p] = self.populationInfDays[time][p] + 1;
-                    if self.populationInfDays[time][p] > self.infectiousPeriod as u32 {
-                        self.populationinf[time][p] == 2;
+                    self.populationInfDays[time][p] += 1;
+                    let strain = self.infectionStrain[p];
+                    if self.populationInfDays[time][p] > self.strains[strain].getInfectiousPeriod() as u32 {
+                        self.populationinf[time][p] = 2;
+                        self.pastInfections[p].push(strain);
+                        self.recoveries[time] += 1;
+                        if rng.gen::<f64>() < self.infectionFatality {
+                            let onset = self.infectionDay[p] + self.incubationPeriod as usize;
+                            let delay = self.deathDelay.sample().max(0.0).round() as usize;
+                            self.deathDay[p] = Some((onset + delay).max(time));
+                        }
                     }
                 }
             }
+            self.fatalities(time);
+            self.seedStrains(time);
+            self.importInfections(time);
+            self.demography(time);
         }
         println!("timestep: {:?}", time)
     }
 
+    // Venues of each category for someone living at home, the nearest one if they take part
+    fn assignVenues(&mut self, home: (f32, f32)) -> Vec<usize> {
+        let mut rng = thread_rng();
+        let mut res = Vec::new();
+        for category in [VenueCategory::Shop, VenueCategory::School, VenueCategory::Workplace, VenueCategory::Leisure] {
+            if rng.gen::<f64>() < category.participation() {
+                if let Some(v) = nearestVenue(&self.venues, category, home.0, home.1) {
+                    res.push(v);
+                }
+            }
+        }
+        res
+    }
+
+    fn cityChanceOf(person: &mut Person) -> f64 {
+        if person.getIsFrom() == Locations::City {
+            return 1.0;
+        }
+        person.getChanceToCity()
+    }
+
+    // Transmission inside venues: everyone in a venue at the same hours mixes, at the venue's rate
+    fn venueTransmission(&mut self, time: usize) {
+        if self.venues.is_empty() {
+            return;
+        }
+        let mut rng = thread_rng();
+        let forcing = self.forcing.multiplier(time);
+
+        // (person, arrival hour) for each venue
+        let mut visitors: Vec<Vec<(usize, usize)>> = vec![Vec::new(); self.venues.len()];
+        let mut occupancy = vec![[0usize;24]; self.venues.len()];
+        for p in 0..self.venueAssignments.len() {
+            if self.populationAlive[time][p] == 0 {
+                continue;
+            }
+            // people from outside the city only come in on some days
+            if rng.gen::<f64>() >= self.cityChance[p] {
+                continue;
+            }
+            for v in self.venueAssignments[p].clone() {
+                let category = self.venues[v].getCategory();
+                if self.closedVenues.contains(&category) || rng.gen::<f64>() >= category.visitChance(time) {
+                    continue;
+                }
+                let openHours = self.venues[v].getOpenHours();
+                let stay = category.visitHours().min(openHours);
+                if stay == 0 {
+                    continue;
+                }
+                let arrival = self.venues[v].getOpenHour() + rng.gen_range(0..=(openHours - stay));
+                // turned away if it is full at any point of the visit
+                if (arrival..arrival + stay).any(|h| occupancy[v][h] >= self.venues[v].getCapacity()) {
+                    continue;
+                }
+                for hour in &mut occupancy[v][arrival..arrival + stay] {
+                    *hour += 1;
+                }
+                visitors[v].push((p, arrival));
+            }
+        }
+
+        for (v, venueVisitors) in visitors.into_iter().enumerate() {
+            let stay = self.venues[v].getCategory().visitHours().min(self.venues[v].getOpenHours());
+            let rate = self.venues[v].getRate();
+            let infectious: Vec<(usize, usize)> = venueVisitors.iter().filter(|(p, _)| self.populationinf[time-1][*p] == 1).cloned().collect();
+            if infectious.is_empty() {
+                continue;
+            }
+            for (s, arrival) in venueVisitors {
+                if self.populationinf[time-1][s] == 1 || self.populationinf[time][s] == 1 {
+                    continue;
+                }
+                // infection pressure from each infectious visitor over the hours shared with them
+                let mut pressures = Vec::new();
+                let mut total = 0.0;
+                for (i, infArrival) in infectious.clone() {
+                    let overlap = stay.saturating_sub(arrival.abs_diff(infArrival));
+                    if overlap == 0 {
+                        continue;
+                    }
+                    let strain = self.infectionStrain[i];
+                    let pressure = rate * self.maskScale * (overlap as f64) * forcing * self.strains[strain].getTransmissibility() * self.spreadChances[i] * self.relativeInfectiousness(i, time-1) * self.susceptibility(s, strain, time-1);
+                    total += pressure;
+                    pressures.push((i, pressure));
+                }
+                if total > 0.0 && rng.gen::<f64>() < 1.0 - (-total).exp() {
+                    // infector picked in proportion to their pressure
+                    let mut pick = rng.gen::<f64>() * total;
+                    let mut infector = pressures[pressures.len() - 1].0;
+                    for (i, pressure) in pressures {
+                        if pick < pressure {
+                            infector = i;
+                            break;
+                        }
+                        pick -= pressure;
+                    }
+                    self.transmit(infector, s, time);
+                    self.venueInfections[time] += 1;
+                }
+            }
+        }
+    }
+
+    // Marks person as newly infected with strain on day by infector (None for seeded cases), the caller sets populationInfDays
+    fn infect(&mut self, person: usize, infector: Option<usize>, strain: usize, day: usize) {
+        if let Some(tree) = self.transmissionTree.as_mut() {
+            let onset = if self.asymptomatic[person] { None } else { Some(day + self.incubationPeriod as usize) };
+            tree.record(person, infector, day, strain, onset);
+        }
+        self.populationinf[day][person] = 1;
+        self.infectionStrain[person] = strain;
+        self.strainIncidence[strain][day] += 1;
+        self.infectionDay[person] = day;
+        self.infectionIndex[person] = self.secondaryCases.len();
+        self.secondaryCases.push(0);
+        self.cohortSize[day] += 1;
+        self.report(person, day);
+    }
+
+    // infector passes their strain on to infectee on day
+    fn transmit(&mut self, infector: usize, infectee: usize, day: usize) {
+        let strain = self.infectionStrain[infector];
+        self.populationInfDays[day][infectee] = 0;
+        self.secondaryCases[self.infectionIndex[infector]] += 1;
+        self.cohortSecondary[self.infectionDay[infector]] += 1;
+        self.infect(infectee, Some(infector), strain, day);
+    }
+
+    // Infects the seed number of random not-infected people for every variant seeded on this day
+    fn seedStrains(&mut self, time: usize) {
+        for s in 1..self.strains.len() {
+            if self.strains[s].getSeedDay() == time {
+                for _ in 0..self.strains[s].getSeedNum() {
+                    if let Some(p) = self.randomUninfected(time) {
+                        self.infect(p, None, s, time);
+                        self.populationInfDays[time][p] = 1;
+                    }
+                }
+            }
+        }
+    }
+
+    // Turns random not-infected people into imported cases and moves them to the import placement
+    fn importInfections(&mut self, time: usize) {
+        let mut rng = rand::thread_rng();
+        for _ in 0..self.importation.count(time) {
+            if let Some(p) = self.randomUninfected(time) {
+                self.infect(p, None, 0, time);
+                self.populationInfDays[time][p] = 1;
+                self.imported[time] += 1;
+                match self.importPlacement {
+                    ImportPlacement::Random => {},
+                    ImportPlacement::At { x, y, radius } => {
+                        let angle = rng.gen::<f32>() * 2.0 * std::f32::consts::PI;
+                        let dist = radius * rng.gen::<f32>().sqrt();
+                        self.populationposvel[time][0][p] = x + dist * angle.cos();
+                        self.populationposvel[time][1][p] = y + dist * angle.sin();
+                    }
+                }
+            }
+        }
+    }
+
+    // Natural deaths from any state, then births into empty slots
+    fn demography(&mut self, time: usize) {
+        let mut rng = rand::thread_rng();
+        for p in 0..self.capacity {
+            if self.populationAlive[time][p] == 1 && rng.gen::<f64>() < self.deathRate {
+                self.populationAlive[time][p] = 0;
+                self.populationinf[time][p] = 0;
+                self.populationInfDays[time][p] = 0;
+                self.deathDay[p] = None;
+                self.deaths[time] += 1;
+            }
+        }
+
+        let numBirths = samplePoisson(self.birthRate * (self.aliveOn(time) as f64));
+        let mut slot = 0;
+        for _ in 0..numBirths {
+            while slot < self.capacity && self.populationAlive[time][slot] == 1 {
+                slot += 1;
+            }
+            if slot == self.capacity {
+                break;
+            }
+            let mut person = Person::random(Personstate::Sus,&self.infectiousness, self.spawnLoc.clone(), self.velocityMinMax.clone());
+            let pos = self.spawnPosition(slot, false);
+            person.setPos(pos.0, pos.1);
+            self.homes[0][slot] = pos.0;
+            self.homes[1][slot] = pos.1;
+            self.waypoints[0][slot] = rng.gen::<f32>() * self.spawnLoc.get(0, 0) as f32;
+            self.waypoints[1][slot] = rng.gen::<f32>() * self.spawnLoc.get(0, 1) as f32;
+            // newborns move as slowly as everyone else under the current lockdowns
+            let mut posvel = person.getPosVel();
+            posvel[2] *= self.speedScale as f32;
+            posvel[3] *= self.speedScale as f32;
+            for (column, value) in self.populationposvel[time].iter_mut().zip(posvel) {
+                column[slot] = value;
+            }
+            self.spreadChances[slot] = person.getSpreadChance();
+            self.asymptomatic[slot] = rng.gen::<f64>() < self.asymptomaticFraction;
+            self.pastInfections[slot] = Vec::new();
+            self.deathDay[slot] = None;
+            // venues near the newborn's home rather than the ones of whoever had the slot before
+            let assigned = self.assignVenues(pos);
+            self.venueAssignments[slot] = assigned;
+            self.cityChance[slot] = Self::cityChanceOf(&mut person);
+            self.populationAlive[time][slot] = 1;
+            self.births[time] += 1;
+        }
+    }
+
+    // Deaths from infection due on day time
+    fn fatalities(&mut self, time: usize) {
+        for p in 0..self.deathDay.len() {
+            if self.deathDay[p] == Some(time) {
+                self.deathDay[p] = None;
+                if self.populationAlive[time][p] == 1 {
+                    self.populationAlive[time][p] = 0;
+                    self.populationinf[time][p] = 0;
+                    self.populationInfDays[time][p] = 0;
+                    self.diseaseDeaths[time] += 1;
+                }
+            }
+        }
+    }
+
+    fn spawnPosition(&mut self, index: usize, infected: bool) -> (f32, f32) {
+        let edges = [self.spawnLoc.get(0, 0) as f32, self.spawnLoc.get(0, 1) as f32];
+        match self.infectedCluster {
+            Some(cluster) if infected => self.spawnDistribution.sampleInCluster(cluster, index, edges),
+            _ => self.spawnDistribution.sample(index, edges)
+        }
+    }
+
+    // 0 after extinction and on days that were never simulated
+    fn aliveOn(&self, day: usize) -> usize {
+        self.populationAlive[day].iter().filter(|a| **a == 1).count()
+    }
+
+    // count as a fraction of the people alive on day, 0 when nobody is
+    fn proportionAlive(&self, count: usize, day: usize) -> f64 {
+        let alive = self.aliveOn(day);
+        if alive == 0 {
+            return 0.0;
+        }
+        (count as f64) / (alive as f64)
+    }
+
+    fn randomUninfected(&mut self, time: usize) -> Option<usize> {
+        let mut rng = rand::thread_rng();
+        for _ in 0..(100 * self.capacity) {
+            let p = rng.gen_range(0..self.capacity);
+            if self.populationinf[time][p] != 1 && self.populationAlive[time][p] == 1 {
+                return Some(p);
+            }
+        }
+        None
+    }
+
+    // Chance a contact with strain infects person given their past infections
+    fn susceptibility(&mut self, person: usize, strain: usize, day: usize) -> f64 {
+        if self.populationinf[day][person] == 1 {
+            return 0.0;
+        }
+        let mut protection: f64 = 0.0;
+        for past in self.pastInfections[person].clone() {
+            protection = protection.max(self.crossImmunity[past][strain]);
+        }
+        1.0 - protection * (1.0 - self.strains[strain].getImmuneEscape())
+    }
+
+    // Decide whether a new infection of person on day gets reported and when
+    fn report(&mut self, person: usize, day: usize) {
+        let mut rng = rand::thread_rng();
+        let mut reportProb = self.symptomaticReportProb;
+        if self.asymptomatic[person] {
+            reportProb = self.asymptomaticReportProb;
+        }
+        if rng.gen::<f64>() < reportProb {
+            let reportDay = day + self.reportingDelay.sample().max(0.0).round() as usize;
+            if reportDay < self.daysRun {
+                self.reportedCases[reportDay] += 1;
+            }
+        }
+    }
+
+    // Profile weight for how long the infector has been infected, scaled for (pre-)symptom status
+    fn relativeInfectiousness(&mut self, infector: usize, day: usize) -> f64 {
+        let daysInfected = self.populationInfDays[day][infector];
+        let weights = &self.profileWeights[self.infectionStrain[infector]];
+        if daysInfected == 0 || daysInfected as usize > weights.len() {
+            return 0.0;
+        }
+        let mut res = weights[(daysInfected - 1) as usize];
+        if self.asymptomatic[infector] {
+            res *= self.asymptomaticScale;
+        } else if daysInfected <= self.incubationPeriod {
+            res *= self.preSymptomaticScale;
+        }
+        res
+    }
+
     pub fn newFrame(&mut self, time: usize) {
         if self.simulated {
             self.wgpuinit.newFrame(self.populationposvel[time][0].clone(), self.populationposvel[time][1].clone(), self.populationinf[time].clone());
@@ -389,19 +1281,112 @@ impl SIRModel {
     
     
 
-    fn findDistance(pos1: Matrix, pos2: &Matrix) -> f64 {
-        let mut res: f64 = f64::powi(pos1.data[0][0]-pos2.data[0][0],2) + f64::powi(pos1.data[0][1]-pos2.data[0][1],2);
-        res.sqrt()
-    }
 }
 
 
-fn boolToU32(data: bool) -> u32 {
-    let mut res: u32 = 0;
-    if data {
-        res = 1;
-    } else {
-        res = 0;
+// What the trainer compares against data
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Observable {
+    Prevalence,
+    // new infections per day
+    Incidence,
+    // infections so far
+    Cumulative,
+    Reported,
+    CumulativeReported,
+    // deaths from infection per day
+    Deaths
+}
+
+// How the simulated agents relate to the real population
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Scaling {
+    None,
+    // agents per unit area, the arena side is derived from the population size
+    Density(f64),
+    // real people per agent, counts are multiplied by this in all outputs
+    AgentWeight(f64)
+}
+
+fn cumulative(counts: &Vec<usize>) -> Vec<usize> {
+    let mut res = Vec::new();
+    let mut total = 0;
+    for c in counts {
+        total += c;
+        res.push(total);
     }
     res
-}
\ No newline at end of file
+}
+
+fn boolToU32(data: bool) -> u32 {
+    u32::from(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Small model on a headless device, None where there is no adapter to run the shaders on
+    fn testModel(days: usize) -> Option<SIRModel> {
+        let wgpuinit = pollster::block_on(WgpuInit::headless())?;
+        Some(SIRModel::new(200, 5.0, 7, 5, 0.5, 0.2, 100.0, 100.0, 1.0, 2.0, days, wgpuinit, Vec::new()))
+    }
+
+    #[test]
+    fn demographyAfterNewRuns() {
+        let Some(mut model) = testModel(5) else { return };
+        model.setDemography(0.01, 0.01, 400);
+        pollster::block_on(model.runSim());
+        let alive = model.getAlive();
+        assert_eq!(alive.len(), 5);
+        assert_eq!(alive[0], 200);
+        assert!(alive.iter().all(|a| *a <= 400));
+    }
+
+    #[test]
+    fn densityPlacesEveryoneInTheNewArena() {
+        let Some(mut model) = testModel(3) else { return };
+        // 200 people at 0.005 per unit area is a 200 by 200 arena
+        model.setScaling(Scaling::Density(0.005));
+        let inside = |x: &f32| (0.0..=200.0).contains(x);
+        assert!(model.populationposvel[0][0].iter().all(inside));
+        assert!(model.populationposvel[0][1].iter().all(inside));
+        assert!(model.homes[0].iter().all(inside));
+        assert!(model.populationposvel[0][0].iter().any(|x| *x > 100.0));
+        pollster::block_on(model.runSim());
+    }
+
+    #[test]
+    fn agentsForRealCounts() {
+        let Some(mut model) = testModel(3) else { return };
+        assert_eq!(model.agentsFor(1000.0, 10000), 20.0);
+        model.setScaling(Scaling::AgentWeight(50.0));
+        assert_eq!(model.agentsFor(100.0, 10000), 2.0);
+        assert_eq!(model.agentsFor(3.0, 10000), 1.0);
+        assert_eq!(model.agentsFor(0.0, 10000), 0.0);
+    }
+
+    #[test]
+    fn addedStrainSeedsAndRuns() {
+        let Some(mut model) = testModel(5) else { return };
+        let strain = model.addStrain(Strain::new(1.5, 7, 0.0, 1, 10));
+        pollster::block_on(model.runSim());
+        let incidence = model.strainIncidence();
+        assert_eq!(incidence.len(), 2);
+        assert_eq!(incidence[strain].len(), 5);
+        assert!(incidence[strain][1] > 0);
+    }
+
+    #[test]
+    fn offspringCountsEveryInfection() {
+        let Some(mut model) = testModel(12) else { return };
+        model.setFatality(1.0, Distribution::Constant(0.0));
+        pollster::block_on(model.runSim());
+        assert!(model.getDiseaseDeaths().iter().sum::<usize>() > 0);
+        let offspring = model.offspringDistribution();
+        let infections: usize = model.newInfections().iter().sum();
+        assert_eq!(offspring.iter().sum::<usize>(), infections);
+        let transmissions: usize = offspring.iter().enumerate().map(|(n, count)| n * count).sum();
+        assert_eq!(transmissions, infections - 5);
+    }
+}
@@ -0,0 +1,123 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufRead};
+
+use rand::{thread_rng,Rng};
+
+use super::distributions::sampleNormal;
+
+// A town people are placed around with a normal spread
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Cluster {
+    x: f32,
+    y: f32,
+    sd: f32,
+    weight: f64
+}
+
+impl Cluster {
+    pub fn new(x: f32, y: f32, sd: f32, weight: f64) -> Cluster {
+        Cluster {x, y, sd, weight}
+    }
+
+    pub fn sample(&mut self) -> (f32, f32) {
+        (self.x + self.sd * sampleNormal() as f32, self.y + self.sd * sampleNormal() as f32)
+    }
+}
+
+// Where people are placed when the population is created
+#[derive(Clone, PartialEq, Debug)]
+pub enum SpawnDistribution {
+    Uniform,
+    Clusters(Vec<Cluster>),
+    // pixel brightness of an image as relative density, cumulative over the pixels in row order
+    Raster { width: u32, height: u32, cumulative: Vec<f64> },
+    // explicit coordinates, person i gets point i (wrapping round)
+    Points(Vec<(f32, f32)>)
+}
+
+impl SpawnDistribution {
+    // Position for person index inside an arena of size edges
+    pub fn sample(&self, index: usize, edges: [f32;2]) -> (f32, f32) {
+        let mut rng = thread_rng();
+        let pos = match self {
+            SpawnDistribution::Uniform => (rng.gen::<f32>() * edges[0], rng.gen::<f32>() * edges[1]),
+            SpawnDistribution::Clusters(clusters) => {
+                let total: f64 = clusters.iter().map(|c| c.weight).sum();
+                let mut pick = rng.gen::<f64>() * total;
+                let mut chosen = clusters.len() - 1;
+                for (i, c) in clusters.iter().enumerate() {
+                    if pick < c.weight {
+                        chosen = i;
+                        break;
+                    }
+                    pick -= c.weight;
+                }
+                clusters[chosen].clone().sample()
+            },
+            SpawnDistribution::Raster { width, height, cumulative } => {
+                let pick = rng.gen::<f64>() * cumulative[cumulative.len() - 1];
+                let pixel = cumulative.partition_point(|c| *c <= pick).min(cumulative.len() - 1);
+                let px = (pixel as u32 % width) as f32 + rng.gen::<f32>();
+                let py = (pixel as u32 / width) as f32 + rng.gen::<f32>();
+                // image rows go down, the arena y axis goes up
+                (px / (*width as f32) * edges[0], (1.0 - py / (*height as f32)) * edges[1])
+            },
+            SpawnDistribution::Points(points) => points[index % points.len()]
+        };
+        (pos.0.max(0.0).min(edges[0]), pos.1.max(0.0).min(edges[1]))
+    }
+
+    // Position inside one cluster, falls back to sample for the other distributions
+    pub fn sampleInCluster(&self, cluster: usize, index: usize, edges: [f32;2]) -> (f32, f32) {
+        match self {
+            SpawnDistribution::Clusters(clusters) => {
+                let pos = clusters[cluster].clone().sample();
+                (pos.0.max(0.0).min(edges[0]), pos.1.max(0.0).min(edges[1]))
+            },
+            _ => self.sample(index, edges)
+        }
+    }
+
+    // Density raster from an image, brighter pixels get more people
+    pub fn fromImage(file_path: &str) -> Result<SpawnDistribution, Box<dyn Error>> {
+        let img = image::open(file_path)?.to_luma8();
+        let (width, height) = img.dimensions();
+        let mut cumulative = Vec::new();
+        let mut total = 0.0;
+        for y in 0..height {
+            for x in 0..width {
+                total += img.get_pixel(x, y)[0] as f64;
+                cumulative.push(total);
+            }
+        }
+        if total <= 0.0 {
+            return Err("density image is completely dark".into());
+        }
+        Ok(SpawnDistribution::Raster { width, height, cumulative })
+    }
+
+    // Reads x,y pairs, one per line
+    pub fn fromCsv(file_path: &str) -> Result<SpawnDistribution, Box<dyn Error>> {
+        let file = File::open(file_path)?;
+        let mut points = Vec::new();
+        let reader = io::BufReader::new(file);
+
+        for line in reader.lines() {
+            let line = line?;
+            let values: Vec<f32> = line
+                .trim()
+                .split(',')
+                .filter_map(|s| s.trim().parse().ok())
+                .collect();
+            if values.len() >= 2 {
+                points.push((values[0], values[1]));
+            }
+        }
+
+        if points.is_empty() {
+            return Err("no coordinates in spawn file".into());
+        }
+        Ok(SpawnDistribution::Points(points))
+    }
+}
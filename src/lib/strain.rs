@@ -0,0 +1,40 @@
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Strain {
+    // multiplies the model's spread rate
+    transmissibility: f64,
+    infectiousPeriod: usize,
+    // fraction of immunity from earlier infections that this strain gets past
+    immuneEscape: f64,
+    seedDay: usize,
+    seedNum: usize
+}
+
+impl Strain {
+    pub fn new(transmissibility: f64, infectiousPeriod: usize, immuneEscape: f64, seedDay: usize, seedNum: usize) -> Strain {
+        Strain {transmissibility, infectiousPeriod, immuneEscape, seedDay, seedNum}
+    }
+
+    pub fn getTransmissibility(&mut self) -> f64 {
+        self.transmissibility
+    }
+
+    pub fn getInfectiousPeriod(&mut self) -> usize {
+        self.infectiousPeriod
+    }
+
+    pub fn setInfectiousPeriod(&mut self, infectiousPeriod: usize) {
+        self.infectiousPeriod = infectiousPeriod;
+    }
+
+    pub fn getImmuneEscape(&mut self) -> f64 {
+        self.immuneEscape
+    }
+
+    pub fn getSeedDay(&mut self) -> usize {
+        self.seedDay
+    }
+
+    pub fn getSeedNum(&mut self) -> usize {
+        self.seedNum
+    }
+}
@@ -1,10 +1,13 @@
 use std::sync::Arc;
 
-use super::sirmodel::SIRModel;
+use nalgebra::DVector;
 
-use super::bayesian::{BayesianOptimization,GaussianProcess};
+use super::{sirmodel::{SIRModel, Observable}, parameter::{Parameter, fromUnitPoint}, loss::Loss, alignment::{DataAlignment, FitWindow, fromCounts}};
 
-use rand::{thread_rng,Rng};
+use super::bayesian::{BayesianOptimization,GaussianProcess,AcquisitionFunction,BatchStrategy};
+use super::kernel::{Kernel, SquaredExponential};
+
+use rand::Rng;
 
 #[derive(PartialEq)]
 pub enum TrainModel {
@@ -21,11 +24,27 @@ pub struct Trainer {
     trainingmodel: TrainModel,
     learningrate: f64,
     expPopSize: usize,
-    simPopSize: usize
+    simPopSize: usize,
+    observable: Observable,
+    parameters: Vec<Parameter>,
+    // GP kernel for the Bayesian trainer, a squared exponential over the parameters if None
+    kernel: Option<Box<dyn Kernel>>,
+    acquisition: AcquisitionFunction,
+    // exploration weight of the lower confidence bound
+    kappa: f64,
+    // points per Bayesian iteration and how many simulations run at once
+    batchSize: usize,
+    batchStrategy: BatchStrategy,
+    workers: usize,
+    loss: Loss,
+    // data the simulations are compared with, missing days are None
+    observed: Vec<Option<f64>>,
+    alignment: DataAlignment
 }
 
 impl Trainer {
 
+    #[allow(clippy::too_many_arguments)]
     pub fn new(baseModel: Arc<std::sync::Mutex<SIRModel>>,
         numModels: usize,
         numEpochs: usize,
@@ -35,66 +54,143 @@ impl Trainer {
         learningrate: f64,
         expPopSize: usize,
         simPopSize: usize) -> Trainer {
-            Trainer {baseModel, numModels, numEpochs, startingData, guesses, trainingmodel, learningrate, expPopSize, simPopSize}
+            let observed = fromCounts(&startingData[0]);
+            Trainer {baseModel, numModels, numEpochs, startingData, guesses, trainingmodel, learningrate, expPopSize, simPopSize, observable: Observable::Prevalence, parameters: vec![Parameter::new("spread", 0.0, 1.0)], kernel: None, acquisition: AcquisitionFunction::ExpectedImprovement, kappa: 1.0, batchSize: 1, batchStrategy: BatchStrategy::KrigingBeliever, workers: 1, loss: Loss::Rmse, observed, alignment: DataAlignment::new(0, FitWindow::All)}
+    }
+
+    // SIRModel parameters being calibrated with their bounds, see SIRModel::setParam
+    pub fn setParameters(&mut self, parameters: Vec<Parameter>) {
+        self.parameters = parameters;
+    }
+
+    pub fn setKernel(&mut self, kernel: Box<dyn Kernel>) {
+        self.kernel = Some(kernel);
+    }
+
+    // Data to fit instead of the starting data, which still gives the number of initial infections
+    pub fn setObserved(&mut self, observed: Vec<Option<f64>>) {
+        self.observed = observed;
+    }
+
+    // Which days of the data are fitted and the simulated day they start on. Simulations run
+    // until the last fitted day
+    pub fn setAlignment(&mut self, alignment: DataAlignment) {
+        self.alignment = alignment;
+    }
+
+    // How simulations are scored against the starting data, by both trainers
+    pub fn setLoss(&mut self, loss: Loss) {
+        loss.validate();
+        self.loss = loss;
     }
 
-    pub async fn train(&mut self,expectedPopSize: usize) ->  f64 {
+    pub fn setAcquisition(&mut self, acquisition: AcquisitionFunction) {
+        self.acquisition = acquisition;
+    }
+
+    // How far the lower confidence bound favours uncertain points, 1.0 by default
+    pub fn setKappa(&mut self, kappa: f64) {
+        self.kappa = kappa;
+    }
+
+    pub fn setBatch(&mut self, batchSize: usize, batchStrategy: BatchStrategy, workers: usize) {
+        self.batchSize = batchSize;
+        self.batchStrategy = batchStrategy;
+        self.workers = workers;
+    }
+
+    // Which simulated series is compared to the starting data
+    pub fn setObservable(&mut self, observable: Observable) {
+        self.observable = observable;
+    }
+
+    // Returns the fitted value of every parameter
+    pub async fn train(&mut self,expectedPopSize: usize) ->  Vec<(String, f64)> {
         let mut rng = rand::thread_rng();
         
             
         if self.trainingmodel == TrainModel::Simple {
-            for ep in 0..self.numEpochs {
-                let mut guess = 0.0;
-                if ep > 0 {
-                    guess = self.guesses[ep - 1];
-                } else {
-                    guess = rng.gen::<f64>();
+            // gradient descent on the loss over every parameter, with central differences taken
+            // on the unit scale of their bounds. The gradient is relative to the loss so the
+            // learning rate works the same for every loss. Starts from the guesses if there is
+            // one per parameter
+            let step = 0.05;
+            let mut units: Vec<f64> = if self.guesses.len() == self.parameters.len() {
+                self.parameters.iter().zip(self.guesses.iter()).map(|(p, g)| p.toUnit(*g)).collect()
+            } else {
+                self.parameters.iter().map(|_| rng.gen::<f64>()).collect()
+            };
+            for _ in 0..self.numEpochs {
+                let mut gradient = vec![0.0; units.len()];
+                for i in 0..units.len() {
+                    let (mut lower, mut upper) = (units.clone(), units.clone());
+                    lower[i] = (units[i] - step).max(0.0);
+                    upper[i] = (units[i] + step).min(1.0);
+                    let lossLower = self.meanLoss(&lower, expectedPopSize).await;
+                    let lossUpper = self.meanLoss(&upper, expectedPopSize).await;
+                    gradient[i] = (lossUpper - lossLower) / (upper[i] - lower[i]) / ((lossUpper + lossLower) / 2.0).max(1e-12);
+                }
+                for i in 0..units.len() {
+                    if gradient[i].is_finite() {
+                        units[i] = (units[i] - self.learningrate * gradient[i]).clamp(0.0, 1.0);
+                    }
                 }
-                let mut errorsSum = 0.0;
-                for i in 0..self.numModels {
-                    let mut baseSimul = self.baseModel.lock().unwrap();
-                    baseSimul.clearOut(self.startingData[0][0]);
-                    baseSimul.setSpread(guess);
-                    baseSimul.setDays(40);
-                    baseSimul.runSim().await;
-                    let infected = baseSimul.propInfected();
-                    //println!("{:?}", infected);
-                    let currentError = Self::error(self.startingData[0].clone(),infected, expectedPopSize);
-                    errorsSum = errorsSum + currentError;
-                }   
-                let errs = errorsSum / (self.numModels as f64);
-                
-                let newGuess = guess + self.learningrate * errs;
-                self.guesses.push(newGuess);
             }
-            return *self.guesses.last().unwrap();
+            fromUnitPoint(&self.parameters, &units)
         } else if self.trainingmodel == TrainModel::Bayesian {
             // Define the mean function and kernel for the Gaussian Process
-            let mean_function = Box::new(|x: f64| 0.0);
-            let kernel_function = Box::new(|x1: f64, x2: f64| (-0.5 * (x1 - x2).powi(2)).exp());
+            let mean_function = Box::new(|_: &DVector<f64>| 0.0);
+            let dims = self.parameters.len();
+            let kernel_function = match &self.kernel {
+                Some(kernel) => kernel.clone(),
+                None => Box::new(SquaredExponential::new(vec![0.2; dims], 1.0))
+            };
 
-            // Create a Gaussian Process for Bayesian Optimization
-            let gp = GaussianProcess::new(mean_function, kernel_function);
+            // Create a Gaussian Process for Bayesian Optimization over the unit box
+            let mut gp = GaussianProcess::new(mean_function, kernel_function);
+            gp.set_input_bounds(DVector::zeros(dims), DVector::from_element(dims, 1.0));
 
             // Define the Bayesian Optimization instance with a kappa value (exploration-exploitation trade-off)
-            let mut bo = BayesianOptimization::new(gp, 1.0,self.baseModel.clone(), self.expPopSize, self.simPopSize);
+            let mut bo = BayesianOptimization::new(gp, self.kappa, self.baseModel.clone(), self.expPopSize, self.simPopSize);
+            bo.setObservable(self.observable);
+            bo.setAcquisition(self.acquisition);
+            bo.setParameters(self.parameters.clone());
+            bo.setBatch(self.batchSize, self.batchStrategy, self.workers);
+            bo.setLoss(self.loss);
+            bo.setObserved(self.observed.clone(), self.alignment);
 
             // Perform Bayesian Optimization with 10 iterations
             let best_point = bo.optimize(self.numEpochs, self.startingData.clone()).await;
 
-            println!("Best Point: {}", best_point);
-            return best_point;
+            println!("Best Point: {:?}", best_point);
+            best_point
         } else {
-            return 0.0;
+            Vec::new()
         }
     }
 
-    fn error(expected: Vec<usize>, simulated: Vec<f64>, expectedPopSize: usize) -> f64 {
-        let mut errsum = 0.0;
-        for n in 0..simulated.len() {
-            errsum = errsum as f64 + (expected[n] as f64 )/(expectedPopSize as f64)- simulated[n] as f64;
+    // Loss averaged over numModels runs at a point of the unit box. The runs use a copy of the
+    // base model so its lock is not held while they are awaited
+    async fn meanLoss(&mut self, point: &[f64], expectedPopSize: usize) -> f64 {
+        let mut baseSimul = self.baseModel.lock().unwrap().clone();
+        // the data counts real people, the model seeds agents
+        let startInfNum = baseSimul.agentsFor(self.startingData[0][0] as f64, expectedPopSize);
+        baseSimul.setParam("startInfNum", startInfNum);
+        for (name, value) in fromUnitPoint(&self.parameters, point) {
+            baseSimul.setParam(&name, value);
+        }
+        baseSimul.setDays(self.alignment.horizon(self.observed.len()));
+        let mut lossSum = 0.0;
+        for _ in 0..self.numModels {
+            baseSimul.reset();
+            baseSimul.runSim().await;
+            let infected = baseSimul.observe(self.observable);
+            // as counts in the observed population
+            let simulated: Vec<f64> = infected.iter().map(|p| p * expectedPopSize as f64).collect();
+            let (observed, simulated) = self.alignment.align(&self.observed, &simulated);
+            lossSum += self.loss.evaluate(&observed, &simulated);
         }
-        errsum / (expected.len() as f64)
+        lossSum / (self.numModels as f64)
     }
 
     
@@ -0,0 +1,186 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::Write;
+
+// One infection. Reinfections of the same person are separate infections
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Infection {
+    pub person: usize,
+    // index of the infection that caused this one, None for initial, seeded and imported cases
+    pub parent: Option<usize>,
+    pub day: usize,
+    pub strain: usize,
+    // day symptoms started, None for asymptomatic infections
+    pub onset: Option<usize>
+}
+
+// Who infected whom, recorded only when asked for as it keeps every infection of the run
+#[derive(Clone, PartialEq, Debug)]
+pub struct TransmissionTree {
+    infections: Vec<Infection>,
+    // latest infection of each person
+    current: Vec<Option<usize>>
+}
+
+impl TransmissionTree {
+    pub fn new(capacity: usize) -> TransmissionTree {
+        TransmissionTree {infections: Vec::new(), current: vec![None;capacity]}
+    }
+
+    // Number of people whose latest infection is tracked
+    pub fn setCapacity(&mut self, capacity: usize) {
+        self.current.resize(capacity, None);
+    }
+
+    // Adds an infection of person on day, caused by the current infection of infector if there is one
+    pub fn record(&mut self, person: usize, infector: Option<usize>, day: usize, strain: usize, onset: Option<usize>) {
+        let parent = infector.and_then(|i| self.current[i]);
+        self.current[person] = Some(self.infections.len());
+        self.infections.push(Infection {person, parent, day, strain, onset});
+    }
+
+    pub fn getInfections(&mut self) -> Vec<Infection> {
+        self.infections.clone()
+    }
+
+    // Writes infectee,infector,day,strain lines, the infector column is empty for roots
+    pub fn exportEdgeList(&mut self, file_path: &str) -> Result<(), Box<dyn Error>> {
+        let mut file = File::create(file_path)?;
+        writeln!(file, "infectee,infector,day,strain")?;
+        for inf in &self.infections {
+            let infector = match inf.parent {
+                Some(parent) => self.infections[parent].person.to_string(),
+                None => String::new()
+            };
+            writeln!(file, "{},{},{},{}", inf.person, infector, inf.day, inf.strain)?;
+        }
+        Ok(())
+    }
+
+    // Newick string with one tree per line and the generation times as branch lengths,
+    // None when there are more than maxInfections as the output gets unreadable
+    pub fn newick(&mut self, maxInfections: usize) -> Option<String> {
+        if self.infections.len() > maxInfections {
+            return None;
+        }
+        let children = self.children();
+        let mut res = String::new();
+        for i in 0..self.infections.len() {
+            if self.infections[i].parent.is_none() {
+                res.push_str(&self.newickNode(i, &children));
+                res.push_str(";\n");
+            }
+        }
+        Some(res)
+    }
+
+    fn newickNode(&self, node: usize, children: &Vec<Vec<usize>>) -> String {
+        let mut res = String::new();
+        if !children[node].is_empty() {
+            let subtrees: Vec<String> = children[node].iter().map(|c| self.newickNode(*c, children)).collect();
+            res.push_str(&format!("({})", subtrees.join(",")));
+        }
+        res.push_str(&self.infections[node].person.to_string());
+        if let Some(parent) = self.infections[node].parent {
+            res.push_str(&format!(":{}", self.infections[node].day - self.infections[parent].day));
+        }
+        res
+    }
+
+    fn children(&self) -> Vec<Vec<usize>> {
+        let mut res = vec![Vec::new(); self.infections.len()];
+        for (i, inf) in self.infections.iter().enumerate() {
+            if let Some(parent) = inf.parent {
+                res[parent].push(i);
+            }
+        }
+        res
+    }
+
+    // Days between infecting and being infected for every transmission
+    pub fn generationTimes(&mut self) -> Vec<usize> {
+        self.infections.iter().filter_map(|inf| inf.parent.map(|p| inf.day - self.infections[p].day)).collect()
+    }
+
+    // Days between the infector's and infectee's symptom onsets, where both had symptoms. These can be negative
+    pub fn serialIntervals(&mut self) -> Vec<i64> {
+        let mut res = Vec::new();
+        for inf in &self.infections {
+            if let Some(parent) = inf.parent {
+                if let (Some(onset), Some(parentOnset)) = (inf.onset, self.infections[parent].onset) {
+                    res.push(onset as i64 - parentOnset as i64);
+                }
+            }
+        }
+        res
+    }
+
+    // Histogram of secondary cases per infection, infections near the end of the run are censored
+    pub fn secondaryCaseDistribution(&mut self) -> Vec<usize> {
+        let children = self.children();
+        let mut res = Vec::new();
+        for c in children {
+            if res.len() <= c.len() {
+                res.resize(c.len() + 1, 0);
+            }
+            res[c.len()] += 1;
+        }
+        res
+    }
+
+    // Number of infections descending from each root infection, including the root
+    pub fn clusterSizes(&mut self) -> Vec<usize> {
+        // parents always come before their children
+        let mut root = vec![0; self.infections.len()];
+        let mut sizes = Vec::new();
+        for i in 0..self.infections.len() {
+            match self.infections[i].parent {
+                Some(parent) => {
+                    root[i] = root[parent];
+                    sizes[root[i]] += 1;
+                },
+                None => {
+                    root[i] = sizes.len();
+                    sizes.push(1);
+                }
+            }
+        }
+        sizes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 0 infects 2 and 3, 3 infects 4, 1 infects nobody, then 2 is reinfected by 4
+    fn example() -> TransmissionTree {
+        let mut tree = TransmissionTree::new(5);
+        tree.record(0, None, 0, 0, Some(5));
+        tree.record(1, None, 0, 0, None);
+        tree.record(2, Some(0), 3, 0, Some(8));
+        tree.record(3, Some(0), 4, 0, Some(7));
+        tree.record(4, Some(3), 6, 0, Some(12));
+        tree.record(2, Some(4), 20, 0, None);
+        tree
+    }
+
+    #[test]
+    fn rootsAndGenerations() {
+        let mut tree = example();
+        let roots: Vec<usize> = tree.getInfections().iter().filter(|i| i.parent.is_none()).map(|i| i.person).collect();
+        assert_eq!(roots, vec![0, 1]);
+        assert_eq!(tree.generationTimes(), vec![3, 4, 2, 14]);
+        assert_eq!(tree.serialIntervals(), vec![3, 2, 5]);
+        assert_eq!(tree.clusterSizes(), vec![5, 1]);
+        // three infections with no secondary cases, two with one and one with two
+        assert_eq!(tree.secondaryCaseDistribution(), vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn newickNestsByInfector() {
+        let mut tree = example();
+        assert_eq!(tree.newick(10), Some(String::from("(2:3,((2:14)4:2)3:4)0;\n1;\n")));
+        assert_eq!(tree.newick(5), None);
+    }
+}
@@ -0,0 +1,173 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufRead};
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum VenueCategory {
+    Shop,
+    School,
+    Workplace,
+    Leisure
+}
+
+impl VenueCategory {
+    // Chance a person with a venue of this category goes there on day (day 0 is a Monday)
+    pub fn visitChance(&self, day: usize) -> f64 {
+        let weekend = day % 7 >= 5;
+        match self {
+            VenueCategory::School | VenueCategory::Workplace => if weekend { 0.0 } else { 1.0 },
+            VenueCategory::Shop => 0.3,
+            VenueCategory::Leisure => if weekend { 0.3 } else { 0.15 }
+        }
+    }
+
+    // Hours spent per visit
+    pub fn visitHours(&self) -> usize {
+        match self {
+            VenueCategory::School => 6,
+            VenueCategory::Workplace => 8,
+            VenueCategory::Shop => 1,
+            VenueCategory::Leisure => 2
+        }
+    }
+
+    // Fraction of people who get a venue of this category
+    pub fn participation(&self) -> f64 {
+        match self {
+            VenueCategory::School => 0.2,
+            VenueCategory::Workplace => 0.5,
+            VenueCategory::Shop => 1.0,
+            VenueCategory::Leisure => 0.6
+        }
+    }
+
+    fn parse(name: &str) -> Option<VenueCategory> {
+        match name.trim().to_lowercase().as_str() {
+            "shop" => Some(VenueCategory::Shop),
+            "school" => Some(VenueCategory::School),
+            "workplace" | "work" => Some(VenueCategory::Workplace),
+            "leisure" => Some(VenueCategory::Leisure),
+            _ => None
+        }
+    }
+}
+
+// A place people gather, everyone inside mixes regardless of position
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Venue {
+    category: VenueCategory,
+    x: f32,
+    y: f32,
+    // most people inside at the same hour
+    capacity: usize,
+    openHour: usize,
+    closeHour: usize,
+    // transmission rate per hour spent together with an infectious visitor
+    rate: f64
+}
+
+impl Venue {
+    pub fn new(category: VenueCategory, x: f32, y: f32, capacity: usize, openHour: usize, closeHour: usize, rate: f64) -> Venue {
+        let closeHour = closeHour.min(24);
+        Venue {category, x, y, capacity, openHour: openHour.min(closeHour), closeHour, rate}
+    }
+
+    pub fn getCategory(&mut self) -> VenueCategory {
+        self.category
+    }
+
+    pub fn getPos(&mut self) -> (f32, f32) {
+        (self.x, self.y)
+    }
+
+    pub fn getCapacity(&mut self) -> usize {
+        self.capacity
+    }
+
+    pub fn getOpenHour(&mut self) -> usize {
+        self.openHour
+    }
+
+    pub fn getOpenHours(&mut self) -> usize {
+        self.closeHour - self.openHour
+    }
+
+    pub fn getRate(&mut self) -> f64 {
+        self.rate
+    }
+
+    // Reads category,x,y,capacity,openHour,closeHour,rate lines, after a header line starting with
+    // category if there is one. Blank lines are skipped, any other line that does not parse is an
+    // error naming the line
+    pub fn fromCsv(file_path: &str) -> Result<Vec<Venue>, Box<dyn Error>> {
+        let file = File::open(file_path)?;
+        let mut venues = Vec::new();
+        let reader = io::BufReader::new(file);
+
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            let values: Vec<&str> = line.trim().split(',').map(|v| v.trim()).collect();
+            if line.trim().is_empty() || (i == 0 && values[0].eq_ignore_ascii_case("category")) {
+                continue;
+            }
+            let malformed = |what: &str| format!("line {} of {}: {}", i + 1, file_path, what);
+            if values.len() < 7 {
+                return Err(malformed("expected category,x,y,capacity,openHour,closeHour,rate").into());
+            }
+            let category = VenueCategory::parse(values[0]).ok_or_else(|| malformed(&format!("unknown category {:?}", values[0])))?;
+            let x: f32 = values[1].parse().map_err(|_| malformed("x is not a number"))?;
+            let y: f32 = values[2].parse().map_err(|_| malformed("y is not a number"))?;
+            let capacity: usize = values[3].parse().map_err(|_| malformed("capacity is not a whole number"))?;
+            let openHour: usize = values[4].parse().map_err(|_| malformed("openHour is not a whole number"))?;
+            let closeHour: usize = values[5].parse().map_err(|_| malformed("closeHour is not a whole number"))?;
+            let rate: f64 = values[6].parse().map_err(|_| malformed("rate is not a number"))?;
+            venues.push(Venue::new(category, x, y, capacity, openHour, closeHour, rate));
+        }
+
+        Ok(venues)
+    }
+}
+
+// Index of the venue of category closest to (x, y)
+pub fn nearestVenue(venues: &[Venue], category: VenueCategory, x: f32, y: f32) -> Option<usize> {
+    let mut best = None;
+    let mut bestDist = f32::MAX;
+    for (i, v) in venues.iter().enumerate() {
+        if v.category != category {
+            continue;
+        }
+        let dist = (v.x - x).powi(2) + (v.y - y).powi(2);
+        if dist < bestDist {
+            bestDist = dist;
+            best = Some(i);
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn readCsv(name: &str, contents: &str) -> Result<Vec<Venue>, Box<dyn Error>> {
+        let path = std::env::temp_dir().join(format!("venues-{}-{}.csv", name, std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        let res = Venue::fromCsv(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        res
+    }
+
+    #[test]
+    fn readsVenuesAfterTheHeader() {
+        let venues = readCsv("ok", "category,x,y,capacity,openHour,closeHour,rate\nshop,1,2,30,9,17,0.5\n\nschool,3.5,4,200,8,15,0.2\n").unwrap();
+        assert_eq!(venues, vec![Venue::new(VenueCategory::Shop, 1.0, 2.0, 30, 9, 17, 0.5), Venue::new(VenueCategory::School, 3.5, 4.0, 200, 8, 15, 0.2)]);
+    }
+
+    #[test]
+    fn malformedRowNamesTheLine() {
+        let err = readCsv("bad", "shop,1,2,30,9,17,0.5\nshop,1,oops,30,9,17,0.5\n").unwrap_err();
+        assert!(err.to_string().starts_with("line 2 of "), "{}", err);
+        assert!(err.to_string().ends_with("y is not a number"), "{}", err);
+        assert!(readCsv("category", "pub,1,2,30,9,17,0.5\n").is_err());
+    }
+}
@@ -1,16 +1,17 @@
 
 
-use wgpu::{Device, Instance, Surface, Adapter, Queue, ShaderModule, ComputePipeline, BufferUsages, util::DeviceExt, BindGroupEntry, BufferDescriptor};
-use winit::{event_loop::EventLoop, window::{Window, WindowBuilder}, dpi::PhysicalSize};
+use wgpu::{Device, Instance, Surface, Adapter, Queue, BufferUsages, util::DeviceExt, BindGroupEntry, BufferDescriptor};
+use winit::dpi::PhysicalSize;
 
-use std::{borrow::Cow, iter, num::{NonZeroU64, NonZeroU32}, array, any::TypeId};
+use std::{borrow::Cow, iter, num::NonZeroU32};
 
 
 
 pub struct WgpuInit {
     pub size: PhysicalSize<u32>,
     pub instance: Instance,
-    pub surface: Surface,
+    // None for headless contexts, which can only run the compute shaders
+    pub surface: Option<Surface>,
     pub adapter: Adapter,
     pub device: Device,
     pub queue: Queue
@@ -18,7 +19,7 @@ pub struct WgpuInit {
 
 impl WgpuInit {
 
-    pub async fn new<'a>( size: PhysicalSize<u32>, instance: Instance, surface: Surface) -> WgpuInit{
+    pub async fn new( size: PhysicalSize<u32>, instance: Instance, surface: Surface) -> WgpuInit{
         
 
         let adapter = instance.request_adapter(&wgpu::RequestAdapterOptions {
@@ -38,13 +39,40 @@ impl WgpuInit {
             None,
         ).await.unwrap();
 
-        WgpuInit { size, instance, surface, adapter, device, queue}
+        WgpuInit { size, instance, surface: Some(surface), adapter, device, queue}
     }
 
-    pub fn newFrame(&mut self, posx: Vec<f32>, posy: Vec<f32>, inf: Vec<u32>) {
-        let mut posxvec: Vec<f32> = posx;
-        let mut posyvec: Vec<f32> = posy;
-        let mut infvec: Vec<u32> = inf;
+    // A context with its own device and queue and no window, for extra simulations running alongside
+    pub async fn headless() -> Option<WgpuInit> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+
+        let adapter = instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            compatible_surface: Option::None,
+            force_fallback_adapter: false,
+        })
+        .await?;
+
+        let ( device , queue) = adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                features: wgpu::Features::empty(),
+                limits: wgpu::Limits::default(),
+            },
+            None,
+        ).await.ok()?;
+
+        Some(WgpuInit { size: PhysicalSize::new(0, 0), instance, surface: None, adapter, device, queue})
+    }
+
+    pub fn newFrame(&self, posx: Vec<f32>, posy: Vec<f32>, inf: Vec<u32>) {
+        let surface = match &self.surface {
+            Some(surface) => surface,
+            None => return
+        };
+        let posxvec: Vec<f32> = posx;
+        let posyvec: Vec<f32> = posy;
+        let infvec: Vec<u32> = inf;
 
         let infBuffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Infected or not array Buffer"),
@@ -69,7 +97,7 @@ impl WgpuInit {
         });
 
 
-        let surface_capabilities = self.surface.get_capabilities(&self.adapter);
+        let surface_capabilities = surface.get_capabilities(&self.adapter);
         let format = surface_capabilities.formats[0];
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
@@ -173,7 +201,7 @@ impl WgpuInit {
         let bindGroup = self.device.create_bind_group(&bindGroupDescriptor);
     
 
-        let frame = self.surface.get_current_texture().unwrap();
+        let frame = surface.get_current_texture().unwrap();
         let view = frame.texture.create_view(&wgpu::TextureViewDescriptor::default());
         let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {label: Some("Render Encoder")});
         {
@@ -197,12 +225,17 @@ impl WgpuInit {
         }
     }
 
-    pub async fn checkInf(&mut self, posx: Vec<f32>, posy: Vec<f32>, inf: Vec<u32>, infRad: f32) -> Vec<u32> {
+    // Returns, for every agent, the index + 1 of an infected agent within infRad (0 if none).
+    // With swept the closest approach since prevposx/prevposy is checked instead of the end positions
+    #[allow(clippy::too_many_arguments)]
+    pub async fn checkInf(&self, posx: Vec<f32>, posy: Vec<f32>, prevposx: Vec<f32>, prevposy: Vec<f32>, inf: Vec<u32>, alive: Vec<u32>, infRad: f32, swept: bool) -> Vec<u32> {
         
         
-        let mut posxvec: Vec<f32> = posx;
-        let mut posyvec: Vec<f32> = posy;
-        let mut infvec: Vec<u32> = inf;
+        let posxvec: Vec<f32> = posx;
+        let posyvec: Vec<f32> = posy;
+        let infvec: Vec<u32> = inf;
+        let mut infectorvec: Vec<u32> = vec![0; infvec.len()];
+        let alivevec: Vec<u32> = alive;
 
         let shader = self.device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: None,
@@ -218,9 +251,10 @@ impl WgpuInit {
 
         let pipeline = self.device.create_compute_pipeline( &pipelineDescriptor);
 
+        let contactParams: [f32;4] = [infRad, if swept { 1.0 } else { 0.0 }, 0.0, 0.0];
         let infRadBuffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Infectious Radius Buffer"),
-            contents: bytemuck::bytes_of(&infRad),
+            contents: bytemuck::cast_slice(&contactParams),
             //contents: bytemuck::cast_slice(&edges[0]),
             usage: BufferUsages::UNIFORM 
         });
@@ -246,9 +280,33 @@ impl WgpuInit {
             usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST
         });
 
-        let resInfBuffer = self.device.create_buffer(& BufferDescriptor {
-            label: Some("Infected or not Results array Buffer"),
-            size: u32_vector_to_bytes(&infvec).len() as u64,
+        let aliveBuffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Alive array Buffer"),
+            contents: u32_vector_to_bytes(&alivevec),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST
+        });
+
+        let prevXPosBuffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Previous X Positions array Buffer"),
+            contents: f32_vector_to_bytes(&prevposx),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST
+        });
+
+        let prevYPosBuffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Previous Y Positions array Buffer"),
+            contents: f32_vector_to_bytes(&prevposy),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST
+        });
+
+        let infectorBuffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Infector array Buffer"),
+            contents: u32_vector_to_bytes(&infectorvec),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST
+        });
+
+        let resInfectorBuffer = self.device.create_buffer(& BufferDescriptor {
+            label: Some("Infector Results array Buffer"),
+            size: u32_vector_to_bytes(&infectorvec).len() as u64,
             usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
             mapped_at_creation: false
         });
@@ -263,6 +321,10 @@ impl WgpuInit {
                 BindGroupEntry {binding: 1, resource: infBuffer.as_entire_binding()},
                 BindGroupEntry {binding: 2, resource: xPosBuffer.as_entire_binding()},
                 BindGroupEntry {binding: 3, resource: yPosBuffer.as_entire_binding()},
+                BindGroupEntry {binding: 4, resource: infectorBuffer.as_entire_binding()},
+                BindGroupEntry {binding: 5, resource: aliveBuffer.as_entire_binding()},
+                BindGroupEntry {binding: 6, resource: prevXPosBuffer.as_entire_binding()},
+                BindGroupEntry {binding: 7, resource: prevYPosBuffer.as_entire_binding()},
             ]
         };
 
@@ -287,17 +349,17 @@ impl WgpuInit {
             pass.dispatch_workgroups(infvec.len() as u32, infvec.len() as u32, 1);
         }
 
-        encoder.copy_buffer_to_buffer(&infBuffer, 0, &resInfBuffer, 0, u32_vector_to_bytes(&infvec).len() as u64);
+        encoder.copy_buffer_to_buffer(&infectorBuffer, 0, &resInfectorBuffer, 0, u32_vector_to_bytes(&infectorvec).len() as u64);
         
         self.queue.submit(iter::once(encoder.finish()));
 
 
         let (tx, rx) = futures_intrusive::channel::shared::oneshot_channel();
 
-        let infSlice = resInfBuffer.slice(..);
+        let infectorSlice = resInfectorBuffer.slice(..);
         
 
-        infSlice.map_async(wgpu::MapMode::Read, move |result| {
+        infectorSlice.map_async(wgpu::MapMode::Read, move |result| {
             tx.send(result).unwrap();
         });
 
@@ -306,25 +368,32 @@ impl WgpuInit {
 
         match rx.receive().await {
             Some(Ok(())) => {
-                let data = infSlice.get_mapped_range();
+                let data = infectorSlice.get_mapped_range();
                 let res = data.chunks_exact(4).map(|b| u32::from_ne_bytes(b.try_into().unwrap())).collect::<Vec<u32>>();
-                infvec = res;
+                infectorvec = res;
                 drop(data);
-                resInfBuffer.unmap();
+                resInfectorBuffer.unmap();
             }
             _ => println!("Something went wrong"),
         }
 
-        infvec
+        infectorvec
     }
 
-    pub async fn moveCol(&mut self, posx: Vec<f32>, posy: Vec<f32>, velx: Vec<f32>, vely: Vec<f32>, edges: [f32;2]) -> [Vec<f32>;4] {
+    // Moves everyone with the movement model in params (see Movement::uniform), returns the new
+    // positions and velocities and the updated random waypoints
+    #[allow(clippy::too_many_arguments)]
+    pub async fn moveCol(&self, posx: Vec<f32>, posy: Vec<f32>, velx: Vec<f32>, vely: Vec<f32>, alive: Vec<u32>, homes: [Vec<f32>;2], waypoints: [Vec<f32>;2], edges: [f32;2], params: [f32;9]) -> ([Vec<f32>;4], [Vec<f32>;2]) {
         
         
         let mut posxvec: Vec<f32> = posx;
         let mut posyvec: Vec<f32> = posy;
         let mut velxvec: Vec<f32> = velx;
         let mut velyvec: Vec<f32> = vely;
+        let alivevec: Vec<u32> = alive;
+        // homes and waypoints go up as interleaved x, y pairs, one buffer each
+        let homevec: Vec<f32> = homes[0].iter().zip(&homes[1]).flat_map(|(x, y)| [*x, *y]).collect();
+        let mut waypointvec: Vec<f32> = waypoints[0].iter().zip(&waypoints[1]).flat_map(|(x, y)| [*x, *y]).collect();
 
         //println!("Bytes are thiss {:?}  wooooo",f32_vector_to_bytes(&posyvec));
         
@@ -388,6 +457,30 @@ impl WgpuInit {
             usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST
         });
 
+        let aliveBuffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Alive array Buffer"),
+            contents: u32_vector_to_bytes(&alivevec),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST
+        });
+
+        let homeBuffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Homes array Buffer"),
+            contents: f32_vector_to_bytes(&homevec),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST
+        });
+
+        let waypointBuffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Waypoints array Buffer"),
+            contents: f32_vector_to_bytes(&waypointvec),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST
+        });
+
+        let paramsBuffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Movement Params Buffer"),
+            contents: bytemuck::cast_slice(&params),
+            usage: BufferUsages::UNIFORM
+        });
+
         let bytesNum = f32_vector_to_bytes(&posxvec).len() as u64;
 
         let resXPosBuffer = self.device.create_buffer(& BufferDescriptor {
@@ -418,6 +511,13 @@ impl WgpuInit {
             mapped_at_creation: false
         });
 
+        let resWaypointBuffer = self.device.create_buffer(& BufferDescriptor {
+            label: Some("Waypoints Results array Buffer"),
+            size: 2 * bytesNum,
+            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            mapped_at_creation: false
+        });
+
         let bindGroupLayout = pipeline.get_bind_group_layout(0);
 
         let bindGroupDescriptor = wgpu::BindGroupDescriptor {
@@ -429,7 +529,11 @@ impl WgpuInit {
                 BindGroupEntry {binding: 2, resource: xPosBuffer.as_entire_binding()},
                 BindGroupEntry {binding: 3, resource: yPosBuffer.as_entire_binding()},
                 BindGroupEntry {binding: 4, resource: xVelBuffer.as_entire_binding()},
-                BindGroupEntry {binding: 5, resource: yVelBuffer.as_entire_binding()}
+                BindGroupEntry {binding: 5, resource: yVelBuffer.as_entire_binding()},
+                BindGroupEntry {binding: 6, resource: aliveBuffer.as_entire_binding()},
+                BindGroupEntry {binding: 7, resource: homeBuffer.as_entire_binding()},
+                BindGroupEntry {binding: 8, resource: waypointBuffer.as_entire_binding()},
+                BindGroupEntry {binding: 9, resource: paramsBuffer.as_entire_binding()}
             ]
         };
 
@@ -458,6 +562,7 @@ impl WgpuInit {
         encoder.copy_buffer_to_buffer(&yPosBuffer, 0, &resYPosBuffer, 0, bytesNum);
         encoder.copy_buffer_to_buffer(&xVelBuffer, 0, &resXVelBuffer, 0, bytesNum);
         encoder.copy_buffer_to_buffer(&yVelBuffer, 0, &resYVelBuffer, 0, bytesNum);
+        encoder.copy_buffer_to_buffer(&waypointBuffer, 0, &resWaypointBuffer, 0, 2 * bytesNum);
         
         self.queue.submit(iter::once(encoder.finish()));
 
@@ -466,11 +571,13 @@ impl WgpuInit {
         let (yPosTx, yPosRx) = futures_intrusive::channel::shared::oneshot_channel();
         let (xVelTx, xVelRx) = futures_intrusive::channel::shared::oneshot_channel();
         let (yVelTx, yVelRx) = futures_intrusive::channel::shared::oneshot_channel();
+        let (waypointTx, waypointRx) = futures_intrusive::channel::shared::oneshot_channel();
 
         let xPosSlice = resXPosBuffer.slice(..);
         let yPosSlice = resYPosBuffer.slice(..);
         let xVelSlice = resXVelBuffer.slice(..);
         let yVelSlice = resYVelBuffer.slice(..);
+        let waypointSlice = resWaypointBuffer.slice(..);
 
         xPosSlice.map_async(wgpu::MapMode::Read, move |result| {
             xPosTx.send(result).unwrap();
@@ -486,6 +593,10 @@ impl WgpuInit {
             yVelTx.send(result).unwrap();
         });
 
+        waypointSlice.map_async(wgpu::MapMode::Read, move |result| {
+            waypointTx.send(result).unwrap();
+        });
+
         self.device.poll(wgpu::Maintain::Wait);
         
 
@@ -534,28 +645,27 @@ impl WgpuInit {
             _ => println!("Something went wrong"),
         }
 
-        [posxvec, posyvec,velxvec, velyvec]
+        match waypointRx.receive().await {
+            Some(Ok(())) => {
+                let data = waypointSlice.get_mapped_range();
+                let res = data.chunks_exact(4).map(|b| f32::from_ne_bytes(b.try_into().unwrap())).collect::<Vec<f32>>();
+                waypointvec = res;
+                drop(data);
+                resWaypointBuffer.unmap();
+            }
+            _ => println!("Something went wrong"),
+        }
+
+        let waypointxvec = waypointvec.iter().step_by(2).copied().collect();
+        let waypointyvec = waypointvec.iter().skip(1).step_by(2).copied().collect();
+        ([posxvec, posyvec,velxvec, velyvec], [waypointxvec, waypointyvec])
     }
 }
 
-fn f32_vector_to_bytes(data: &Vec<f32>) -> &[u8] {
-    let data_as_slice: &[f32] = &data;
-    let bytes: &[u8] = unsafe {
-        std::slice::from_raw_parts(
-            data_as_slice.as_ptr() as *const u8,
-            data_as_slice.len() * std::mem::size_of::<f32>(),
-        )
-    };
-    bytes
+fn f32_vector_to_bytes(data: &[f32]) -> &[u8] {
+    bytemuck::cast_slice(data)
 }
 
-fn u32_vector_to_bytes(data: &Vec<u32>) -> &[u8] {
-    let data_as_slice: &[u32] = &data;
-    let bytes: &[u8] = unsafe {
-        std::slice::from_raw_parts(
-            data_as_slice.as_ptr() as *const u8,
-            data_as_slice.len() * std::mem::size_of::<u32>(),
-        )
-    };
-    bytes
-}
\ No newline at end of file
+fn u32_vector_to_bytes(data: &[u32]) -> &[u8] {
+    bytemuck::cast_slice(data)
+}